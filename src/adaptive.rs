@@ -0,0 +1,112 @@
+use crate::checkpoint::{self, Decoder};
+use crate::color::Color;
+use crate::tonemap::PostProcess;
+
+/// Running mean and variance of the luminance of the samples of a pixel
+/// (Welford's algorithm), from which adaptive sampling decides when the
+/// pixel has converged.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PixelStatistics {
+    pub count: u32,
+    mean: f32,
+    squared_deviations: f32,
+}
+
+impl PixelStatistics {
+    pub fn add(&mut self, color: Color) {
+        let value = 0.2126 * color.red + 0.7152 * color.green + 0.0722 * color.blue;
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f32;
+        self.squared_deviations += delta * (value - self.mean);
+    }
+
+    /// Unbiased variance of the samples.
+    pub fn variance(&self) -> f32 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        self.squared_deviations / (self.count - 1) as f32
+    }
+
+    /// Estimated error of the displayed value: the standard error of the
+    /// mean carried through the exposure, tone mapping and encoding of
+    /// `post`, so that noise counts as much as it shows, little in bright
+    /// pixels clipped to white and much in dark ones.
+    pub fn error(&self, post: &PostProcess) -> f32 {
+        if self.count < 2 {
+            return f32::INFINITY;
+        }
+        let standard_error = (self.variance() / self.count as f32).sqrt();
+        let high = post.display_luminance(self.mean + standard_error);
+        let low = post.display_luminance((self.mean - standard_error).max(0.0));
+        (high - low) / 2.0
+    }
+
+    /// Appends the statistics to a checkpoint.
+    pub fn encode(&self, bytes: &mut Vec<u8>) {
+        checkpoint::put_u32(bytes, self.count);
+        checkpoint::put_f32(bytes, self.mean);
+        checkpoint::put_f32(bytes, self.squared_deviations);
+    }
+
+    pub fn decode(decoder: &mut Decoder) -> Result<PixelStatistics, String> {
+        Ok(PixelStatistics { count: decoder.u32()?, mean: decoder.f32()?, squared_deviations: decoder.f32()? })
+    }
+}
+
+/// Colour of a sample count in a heatmap, from blue for `min` samples
+/// through green to red for `max` samples.
+pub fn heatmap_color(count: u16, min: u16, max: u16) -> Color {
+    let t = if max > min {
+        (f32::from(count.clamp(min, max) - min) / f32::from(max - min)).clamp(0.0, 1.0)
+    } else {
+        1.0
+    };
+    if t < 0.5 {
+        Color::new(0.0, 2.0 * t, 1.0 - 2.0 * t)
+    } else {
+        Color::new(2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::RenderSettings;
+
+    #[test]
+    fn test_pixel_statistics() {
+        let post = PostProcess::new(&RenderSettings::default());
+        let mut statistics = PixelStatistics::default();
+        assert_eq!(statistics.error(&post), f32::INFINITY);
+        for value in &[1.0, 2.0, 3.0, 4.0] {
+            statistics.add(Color::new(*value, *value, *value));
+        }
+        assert!((statistics.mean - 2.5).abs() < 1e-5);
+        assert!((statistics.variance() - 5.0 / 3.0).abs() < 1e-5);
+        // All of these samples show as white.
+        assert_eq!(statistics.error(&post), 0.0);
+        let statistics_of = |values: &[f32]| {
+            let mut statistics = PixelStatistics::default();
+            for value in values {
+                statistics.add(Color::new(*value, *value, *value));
+            }
+            statistics
+        };
+        let flat = statistics_of(&[0.3; 4]);
+        assert_eq!(flat.error(&post), 0.0);
+        let noisy = statistics_of(&[0.1, 0.2, 0.3, 0.4]);
+        assert!(noisy.error(&post) > flat.error(&post));
+        // The same noise shows more in darker pixels.
+        let dark = statistics_of(&[0.01, 0.02, 0.03, 0.04]);
+        let bright = statistics_of(&[0.51, 0.52, 0.53, 0.54]);
+        assert!(dark.error(&post) > bright.error(&post));
+    }
+    #[test]
+    fn test_heatmap_color() {
+        assert_eq!(heatmap_color(16, 16, 256).blue, 1.0);
+        assert_eq!(heatmap_color(256, 16, 256).red, 1.0);
+        assert_eq!(heatmap_color(136, 16, 256).green, 1.0);
+    }
+}
@@ -0,0 +1,415 @@
+use crate::checkpoint::{self, Decoder};
+use crate::color::Color;
+use crate::exr::Channel;
+use crate::film::{Film, Filter, PixelBounds};
+use crate::material::Lobe;
+use crate::settings::{AovKind, FilterKind};
+use crate::spectrum::Radiance;
+use crate::vec3::Vec3;
+
+/// What the camera ray of a sample hit first.
+#[derive(Copy, Clone, Debug)]
+pub struct FirstHit {
+    pub distance: f32,
+    pub position: Vec3,
+    pub normal: Vec3,
+    /// Index of the object among those of the scene.
+    pub object: usize,
+}
+
+/// How the light of a path splits, and what it hit first, from which the
+/// AOVs of a sample are made.
+#[derive(Copy, Clone, Debug)]
+pub struct PathRecord<R> {
+    pub first_hit: Option<FirstHit>,
+    /// Weight of the first scattering event, or the light of the sky for
+    /// rays missing the scene.
+    pub albedo: Color,
+    pub direct: R,
+    pub indirect: R,
+    pub diffuse: R,
+    pub specular: R,
+    pub transmission: R,
+    first_lobe: Option<Lobe>,
+}
+
+impl<R: Radiance> PathRecord<R> {
+    pub fn new(wavelengths: &R::Wavelengths) -> PathRecord<R> {
+        let black = R::from_color(Color::black(), wavelengths);
+        PathRecord {
+            first_hit: None,
+            albedo: Color::black(),
+            direct: black,
+            indirect: black,
+            diffuse: black,
+            specular: black,
+            transmission: black,
+            first_lobe: None,
+        }
+    }
+
+    /// Records the first scattering event of the path, by which the light
+    /// found later is split between the lobes.
+    pub fn scattered(&mut self, lobe: Lobe) {
+        self.first_lobe.get_or_insert(lobe);
+    }
+
+    /// Records light reaching the camera after `bounces` scattering events.
+    /// Light seen without scattering, from emitters and the sky, goes to no
+    /// lobe, so that the lobes add up to the image less that light.
+    pub fn add(&mut self, light: R, bounces: u32) {
+        if bounces <= 1 {
+            self.direct += light;
+        } else {
+            self.indirect += light;
+        }
+        match self.first_lobe {
+            Some(Lobe::Diffuse) => self.diffuse += light,
+            Some(Lobe::Specular) => self.specular += light,
+            Some(Lobe::Transmission) => self.transmission += light,
+            None => {}
+        }
+    }
+
+    pub fn to_color(self, wavelengths: &R::Wavelengths) -> PathRecord<Color> {
+        PathRecord {
+            first_hit: self.first_hit,
+            albedo: self.albedo,
+            direct: self.direct.to_color(wavelengths),
+            indirect: self.indirect.to_color(wavelengths),
+            diffuse: self.diffuse.to_color(wavelengths),
+            specular: self.specular.to_color(wavelengths),
+            transmission: self.transmission.to_color(wavelengths),
+            first_lobe: self.first_lobe,
+        }
+    }
+}
+
+impl PathRecord<Color> {
+    /// The record with the light of each pass times `weight`, the part of
+    /// it the camera lets through to the image.
+    pub fn weighted(self, weight: f32) -> PathRecord<Color> {
+        PathRecord {
+            direct: weight * self.direct,
+            indirect: weight * self.indirect,
+            diffuse: weight * self.diffuse,
+            specular: weight * self.specular,
+            transmission: weight * self.transmission,
+            ..self
+        }
+    }
+}
+
+/// Object of the sample nearest to the centre of a pixel, numbered from 1.
+#[derive(Copy, Clone, Debug)]
+struct IdSample {
+    distance: f32,
+    object: usize,
+}
+
+/// Accumulates the AOVs over a rectangle of pixels, like `Film` does the
+/// image. Light passes and the albedo go through the filter of the image,
+/// so that the passes add up to it, while geometric AOVs only average the
+/// samples within each pixel that hit the scene; identifiers are those of
+/// the sample nearest to the centre of the pixel.
+#[derive(Clone, Debug)]
+pub struct AovFilm {
+    x0: u32,
+    y0: u32,
+    width: u32,
+    height: u32,
+    albedo: Film,
+    direct: Film,
+    indirect: Film,
+    diffuse: Film,
+    specular: Film,
+    transmission: Film,
+    depth: Film,
+    position: Film,
+    normal: Film,
+    ids: Vec<Option<IdSample>>,
+}
+
+fn to_color(vector: Vec3) -> Color {
+    Color::new(vector.x, vector.y, vector.z)
+}
+
+impl AovFilm {
+    pub fn new(width: u32, height: u32, filter: Filter) -> AovFilm {
+        AovFilm::new_tile(0, 0, width, height, (width, height), filter)
+    }
+
+    /// AOVs of the pixels from `(x0, y0)` to `(x1, y1)` excluded and of the
+    /// margin reached by the filter, within an image of the given size.
+    pub fn new_tile(x0: u32, y0: u32, x1: u32, y1: u32, image_size: (u32, u32), filter: Filter) -> AovFilm {
+        let light = Film::new_tile(x0, y0, x1, y1, image_size, filter);
+        let geometry = Film::new(x0, y0, x1 - x0, y1 - y0, Filter::new(FilterKind::Box, 0.5));
+        AovFilm {
+            x0,
+            y0,
+            width: x1 - x0,
+            height: y1 - y0,
+            albedo: light.clone(),
+            direct: light.clone(),
+            indirect: light.clone(),
+            diffuse: light.clone(),
+            specular: light.clone(),
+            transmission: light,
+            depth: geometry.clone(),
+            position: geometry.clone(),
+            normal: geometry,
+            ids: vec![None; ((x1 - x0) * (y1 - y0)) as usize],
+        }
+    }
+
+    /// Adds a sample taken at `(x, y)` in image coordinates, filtered
+    /// within `view` as by `Film::add_sample`.
+    pub fn add_sample(&mut self, x: f32, y: f32, view: PixelBounds, path: &PathRecord<Color>) {
+        self.albedo.add_sample(x, y, view, path.albedo);
+        self.direct.add_sample(x, y, view, path.direct);
+        self.indirect.add_sample(x, y, view, path.indirect);
+        self.diffuse.add_sample(x, y, view, path.diffuse);
+        self.specular.add_sample(x, y, view, path.specular);
+        self.transmission.add_sample(x, y, view, path.transmission);
+        // Rounding may put a sample on the far edge of its pixel.
+        let i = (x as u32).clamp(self.x0, self.x0 + self.width - 1);
+        let j = (y as u32).clamp(self.y0, self.y0 + self.height - 1);
+        let index = ((j - self.y0) * self.width + (i - self.x0)) as usize;
+        let (dx, dy) = (x - i as f32 - 0.5, y - j as f32 - 0.5);
+        let distance = dx * dx + dy * dy;
+        let object = match path.first_hit {
+            Some(hit) => {
+                self.depth.add_sample(x, y, view, Color::white() * hit.distance);
+                self.position.add_sample(x, y, view, to_color(hit.position));
+                self.normal.add_sample(x, y, view, to_color(hit.normal));
+                hit.object + 1
+            }
+            None => 0,
+        };
+        if self.ids[index].is_none_or(|nearest| distance < nearest.distance) {
+            self.ids[index] = Some(IdSample { distance, object });
+        }
+    }
+
+    /// Adds the AOVs of `tile`, which must lie within these.
+    pub fn merge(&mut self, tile: &AovFilm) {
+        self.albedo.merge(&tile.albedo);
+        self.direct.merge(&tile.direct);
+        self.indirect.merge(&tile.indirect);
+        self.diffuse.merge(&tile.diffuse);
+        self.specular.merge(&tile.specular);
+        self.transmission.merge(&tile.transmission);
+        self.depth.merge(&tile.depth);
+        self.position.merge(&tile.position);
+        self.normal.merge(&tile.normal);
+        for j in 0..tile.height {
+            for i in 0..tile.width {
+                let index = ((tile.y0 + j - self.y0) * self.width + (tile.x0 + i - self.x0)) as usize;
+                self.ids[index] = tile.ids[(j * tile.width + i) as usize];
+            }
+        }
+    }
+
+    fn films_mut(&mut self) -> [&mut Film; 9] {
+        [
+            &mut self.albedo, &mut self.direct, &mut self.indirect, &mut self.diffuse, &mut self.specular,
+            &mut self.transmission, &mut self.depth, &mut self.position, &mut self.normal,
+        ]
+    }
+
+    /// Appends the AOVs to a checkpoint.
+    pub fn encode(&self, bytes: &mut Vec<u8>) {
+        for film in &[
+            &self.albedo, &self.direct, &self.indirect, &self.diffuse, &self.specular,
+            &self.transmission, &self.depth, &self.position, &self.normal,
+        ] {
+            film.encode(bytes);
+        }
+        for id in &self.ids {
+            match id {
+                Some(id) => {
+                    checkpoint::put_u32(bytes, id.object as u32);
+                    checkpoint::put_f32(bytes, id.distance);
+                }
+                None => checkpoint::put_u32(bytes, u32::MAX),
+            }
+        }
+    }
+
+    /// Reads back the AOVs written by `encode` for AOVs of this size.
+    pub fn decode(&mut self, decoder: &mut Decoder) -> Result<(), String> {
+        for film in self.films_mut().iter_mut() {
+            film.decode(decoder)?;
+        }
+        for id in self.ids.iter_mut() {
+            *id = match decoder.u32()? {
+                u32::MAX => None,
+                object => Some(IdSample { distance: decoder.f32()?, object: object as usize }),
+            };
+        }
+        Ok(())
+    }
+
+    pub fn albedo(&self, i: u32, j: u32) -> Color {
+        self.albedo.pixel(i, j)
+    }
+
+    /// Normal of pixel `(i, j)`, zero where the scene was missed.
+    pub fn normal(&self, i: u32, j: u32) -> Vec3 {
+        let normal = self.normal.pixel(i, j);
+        Vec3::new(normal.red, normal.green, normal.blue)
+    }
+
+    /// Depth of pixel `(i, j)`, zero where the scene was missed.
+    pub fn depth(&self, i: u32, j: u32) -> f32 {
+        self.depth.pixel(i, j).red
+    }
+
+    /// Layer of the given AOV, row by row from the top. Material IDs are
+    /// the `material_numbers` of the objects, see `Scene::material_numbers`.
+    pub fn layer(&self, kind: AovKind, material_numbers: &[u32]) -> Layer {
+        let name = kind.name();
+        let vector = |color: Color| vec![color.red, color.green, color.blue];
+        match kind {
+            AovKind::Depth => Layer::new(name, &["Z"], self.film_values(&self.depth, |color| vec![color.red])),
+            AovKind::Position => Layer::new(name, &["X", "Y", "Z"], self.film_values(&self.position, vector)),
+            AovKind::Normal => Layer::new(name, &["X", "Y", "Z"], self.film_values(&self.normal, vector)),
+            AovKind::ObjectId => Layer::new(name, &["id"], self.id_values(None)),
+            AovKind::MaterialId => Layer::new(name, &["id"], self.id_values(Some(material_numbers))),
+            AovKind::Albedo => Layer::from_film(name, &self.albedo),
+            AovKind::Direct => Layer::from_film(name, &self.direct),
+            AovKind::Indirect => Layer::from_film(name, &self.indirect),
+            AovKind::Diffuse => Layer::from_film(name, &self.diffuse),
+            AovKind::Specular => Layer::from_film(name, &self.specular),
+            AovKind::Transmission => Layer::from_film(name, &self.transmission),
+        }
+    }
+
+    fn film_values(&self, film: &Film, channels: impl Fn(Color) -> Vec<f32>) -> Vec<Vec<f32>> {
+        let mut values = Vec::new();
+        for j in (self.y0..self.y0 + self.height).rev() {
+            for i in self.x0..self.x0 + self.width {
+                for (channel, value) in channels(film.pixel(i, j)).into_iter().enumerate() {
+                    if values.len() <= channel {
+                        values.push(Vec::with_capacity((self.width * self.height) as usize));
+                    }
+                    values[channel].push(value);
+                }
+            }
+        }
+        values
+    }
+
+    /// Objects numbered from 1, or their materials if numbered, 0 where
+    /// the scene was missed.
+    fn id_values(&self, material_numbers: Option<&[u32]>) -> Vec<Vec<f32>> {
+        let mut values = Vec::with_capacity(self.ids.len());
+        for row in self.ids.chunks(self.width as usize).rev() {
+            for id in row {
+                let object = id.map_or(0, |id| id.object);
+                let value = match material_numbers {
+                    Some(numbers) if object > 0 => numbers[object - 1] as usize,
+                    _ => object,
+                };
+                values.push(value as f32);
+            }
+        }
+        vec![values]
+    }
+}
+
+/// Linear float image made of named channels, such as the layer of an AOV.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layer {
+    pub name: &'static str,
+    pub channel_names: &'static [&'static str],
+    /// Values of each channel row by row from the top.
+    pub channels: Vec<Vec<f32>>,
+}
+
+impl Layer {
+    fn new(name: &'static str, channel_names: &'static [&'static str], channels: Vec<Vec<f32>>) -> Layer {
+        Layer { name, channel_names, channels }
+    }
+
+    /// RGB layer of the filtered colours of `film`.
+    pub fn from_film(name: &'static str, film: &Film) -> Layer {
+        let mut channels: Vec<_> = (0..3).map(|_| Vec::with_capacity((film.width * film.height) as usize)).collect();
+        for j in (film.y0..film.y0 + film.height).rev() {
+            for i in film.x0..film.x0 + film.width {
+                let color = film.pixel(i, j);
+                channels[0].push(color.red);
+                channels[1].push(color.green);
+                channels[2].push(color.blue);
+            }
+        }
+        Layer::new(name, &["R", "G", "B"], channels)
+    }
+
+    /// Channels to write, named `layer.channel` unless `prefixed` is false.
+    pub fn exr_channels(&self, prefixed: bool) -> Vec<Channel<'_>> {
+        self.channel_names.iter().zip(self.channels.iter()).map(|(channel, values)| Channel {
+            name: if prefixed { format!("{}.{}", self.name, channel) } else { channel.to_string() },
+            values,
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(object: usize) -> Option<FirstHit> {
+        Some(FirstHit {
+            distance: 2.0,
+            position: Vec3::new(1.0, 2.0, 3.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            object,
+        })
+    }
+
+    #[test]
+    fn test_path_record() {
+        let mut path = PathRecord::<Color>::new(&());
+        path.add(Color::white(), 0);
+        path.scattered(Lobe::Specular);
+        path.add(Color::white() * 0.5, 1);
+        path.scattered(Lobe::Diffuse);
+        path.add(Color::white() * 0.25, 2);
+        assert_eq!(path.direct.red, 1.5);
+        assert_eq!(path.indirect.red, 0.25);
+        // The light is split by the first bounce.
+        assert_eq!(path.specular.red, 0.75);
+        assert_eq!(path.diffuse.red, 0.0);
+    }
+    #[test]
+    fn test_aov_film() {
+        let filter = Filter::new(FilterKind::Tent, 1.0);
+        let mut film = AovFilm::new(2, 1, filter);
+        let mut left = AovFilm::new_tile(0, 0, 1, 1, (2, 1), filter);
+        let mut right = AovFilm::new_tile(1, 0, 2, 1, (2, 1), filter);
+        let mut path = PathRecord::<Color>::new(&());
+        path.first_hit = hit(4);
+        path.direct = Color::white();
+        left.add_sample(0.4, 0.5, (0, 0, 2, 1), &path);
+        path.first_hit = hit(2);
+        left.add_sample(0.9, 0.5, (0, 0, 2, 1), &path);
+        path.first_hit = None;
+        right.add_sample(1.5, 0.5, (0, 0, 2, 1), &path);
+        film.merge(&left);
+        film.merge(&right);
+
+        assert_eq!(film.layer(AovKind::ObjectId, &[]).channels, vec![vec![5.0, 0.0]]);
+        let material_numbers = [1, 1, 2, 2, 3];
+        assert_eq!(film.layer(AovKind::MaterialId, &material_numbers).channels, vec![vec![3.0, 0.0]]);
+        // Only the hits within the pixel count for the geometry.
+        assert_eq!(film.layer(AovKind::Depth, &[]).channels, vec![vec![2.0, 0.0]]);
+        let normal = film.layer(AovKind::Normal, &[]);
+        assert_eq!(normal.channel_names, &["X", "Y", "Z"]);
+        assert_eq!(normal.channels[2], vec![1.0, 0.0]);
+        let direct = film.layer(AovKind::Direct, &[]);
+        assert_eq!(direct.channels[0], vec![1.0, 1.0]);
+        let channels = direct.exr_channels(true);
+        assert_eq!(channels[1].name, "direct.G");
+    }
+}
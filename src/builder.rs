@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::camera::{Camera, EquirectangularCamera, FisheyeCamera, OrthographicCamera, PerspectiveCamera};
+use crate::color::Color;
+use crate::hitable::{Plane, Quad, Scene, Sphere};
+use crate::material::{DiffuseLight, Material, MaterialRef};
+use crate::vec3::Vec3;
+
+enum Shape {
+    Sphere { center: Vec3, radius: f32 },
+    Plane { origin: Vec3, normal: Vec3 },
+    Quad { origin: Vec3, edge_u: Vec3, edge_v: Vec3 },
+}
+
+const Z_UP: Vec3 = Vec3 { x: 0.0, y: 0.0, z: 1.0 };
+
+struct Object {
+    name: String,
+    shape: Shape,
+    material: String,
+}
+
+/// Builds a scene and its camera step by step. Materials are registered
+/// once under a name and objects refer to them by name, so that they share
+/// a single instance, which registering the name again replaces for all of
+/// them. Materials cannot be edited once built into the scene: its objects
+/// keep the instances they were built with, so replacing a material means
+/// building the scene again. Objects are named too, for `Scene::object`.
+///
+/// ```
+/// use raytracer::SceneBuilder;
+/// use raytracer::color::Color;
+/// use raytracer::material::Lambertian;
+/// use raytracer::vec3::Vec3;
+///
+/// let (scene, camera) = SceneBuilder::new()
+///     .material("clay", Lambertian::new(Color::new(0.8, 0.6, 0.5)))
+///     .sphere("ball", Vec3::new(0.0, 0.0, 1.0), 1.0, "clay")
+///     .plane("ground", Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), "clay")
+///     .sphere_light("sun", Vec3::new(0.0, -10.0, 20.0), 5.0, Color::white() * 4.0)
+///     .look_at(Vec3::new(0.0, -8.0, 2.0), Vec3::new(0.0, 0.0, 1.0), 30.0, 1.5)
+///     .build()
+///     .unwrap();
+/// assert_eq!(scene.hitlist.len(), 3);
+/// assert!(scene.object("ball").is_some());
+/// ```
+#[derive(Default)]
+pub struct SceneBuilder {
+    materials: HashMap<String, MaterialRef>,
+    objects: Vec<Object>,
+    camera: Option<Box<dyn Camera + Send + Sync>>,
+}
+
+impl SceneBuilder {
+    pub fn new() -> SceneBuilder {
+        SceneBuilder::default()
+    }
+
+    /// Registers `material` under `name`, replacing the material of that
+    /// name for every object of the scene built next.
+    pub fn material<M: Material + Send + Sync + 'static>(mut self, name: &str, material: M) -> SceneBuilder {
+        self.materials.insert(name.to_string(), Arc::new(material));
+        self
+    }
+
+    /// The material registered under `name`, to share it with objects built
+    /// outside of the builder.
+    pub fn material_ref(&self, name: &str) -> Option<MaterialRef> {
+        self.materials.get(name).cloned()
+    }
+
+    fn object(mut self, name: &str, shape: Shape, material: &str) -> SceneBuilder {
+        self.objects.push(Object { name: name.to_string(), shape, material: material.to_string() });
+        self
+    }
+
+    pub fn sphere(self, name: &str, center: Vec3, radius: f32, material: &str) -> SceneBuilder {
+        self.object(name, Shape::Sphere { center, radius }, material)
+    }
+
+    /// Plane through `origin`, seen from the side opposite to `normal`.
+    pub fn plane(self, name: &str, origin: Vec3, normal: Vec3, material: &str) -> SceneBuilder {
+        self.object(name, Shape::Plane { origin, normal }, material)
+    }
+
+    /// Parallelogram spanned by `edge_u` and `edge_v` from the corner
+    /// `origin`.
+    pub fn quad(self, name: &str, origin: Vec3, edge_u: Vec3, edge_v: Vec3, material: &str) -> SceneBuilder {
+        self.object(name, Shape::Quad { origin, edge_u, edge_v }, material)
+    }
+
+    /// Sphere emitting `emit`, with a material of the same name.
+    pub fn sphere_light(self, name: &str, center: Vec3, radius: f32, emit: Color) -> SceneBuilder {
+        self.material(name, DiffuseLight::new(emit)).sphere(name, center, radius, name)
+    }
+
+    /// Parallelogram emitting `emit`, with a material of the same name.
+    pub fn quad_light(self, name: &str, origin: Vec3, edge_u: Vec3, edge_v: Vec3, emit: Color) -> SceneBuilder {
+        self.material(name, DiffuseLight::new(emit)).quad(name, origin, edge_u, edge_v, name)
+    }
+
+    pub fn camera<C: Camera + Send + Sync + 'static>(mut self, camera: C) -> SceneBuilder {
+        self.camera = Some(Box::new(camera));
+        self
+    }
+
+    /// Pinhole camera at `look_from` looking at `look_at` with the z axis
+    /// up, seeing `vertical_fov` degrees from the bottom to the top of an
+    /// image `aspect` times wider than high.
+    pub fn look_at(self, look_from: Vec3, look_at: Vec3, vertical_fov: f32, aspect: f32) -> SceneBuilder {
+        let focus_dist = (look_from - look_at).length();
+        self.camera(PerspectiveCamera::new(look_from, look_at, Z_UP, vertical_fov, aspect, 0.0, focus_dist))
+    }
+
+    /// Parallel projection from the plane through `look_from` towards
+    /// `look_at` with the z axis up, seeing `height` units from the bottom
+    /// to the top of an image `aspect` times wider than high.
+    pub fn orthographic(self, look_from: Vec3, look_at: Vec3, height: f32, aspect: f32) -> SceneBuilder {
+        self.camera(OrthographicCamera::new(look_from, look_at, Z_UP, height, aspect))
+    }
+
+    /// Fisheye camera at `look_from` looking at `look_at` with the z axis
+    /// up, seeing `field_of_view` degrees from the bottom to the top.
+    pub fn fisheye(self, look_from: Vec3, look_at: Vec3, field_of_view: f32, aspect: f32) -> SceneBuilder {
+        self.camera(FisheyeCamera::new(look_from, look_at, Z_UP, field_of_view, aspect))
+    }
+
+    /// Panorama of everything around `look_from`, centred on `look_at`,
+    /// with the z axis up.
+    pub fn panorama(self, look_from: Vec3, look_at: Vec3) -> SceneBuilder {
+        self.camera(EquirectangularCamera::new(look_from, look_at, Z_UP))
+    }
+
+    /// The scene and its camera, unless the camera is missing, an object
+    /// uses a material that was not registered, or two objects have the
+    /// same name.
+    pub fn build(self) -> Result<(Scene, Box<dyn Camera + Send + Sync>), String> {
+        let camera = self.camera.ok_or_else(|| String::from("the scene has no camera"))?;
+        let mut scene = Scene::new();
+        for object in self.objects {
+            let material = self.materials.get(&object.material)
+                .ok_or_else(|| format!("{} uses the unknown material {}", object.name, object.material))?
+                .clone();
+            if scene.names.insert(object.name.clone(), scene.hitlist.len()).is_some() {
+                return Err(format!("two objects are named {}", object.name));
+            }
+            scene.hitlist.push(match object.shape {
+                Shape::Sphere { center, radius } => Box::new(Sphere::new(center, radius, material)),
+                Shape::Plane { origin, normal } => Box::new(Plane::new(origin, normal, material)),
+                Shape::Quad { origin, edge_u, edge_v } => Box::new(Quad::new(origin, edge_u, edge_v, material)),
+            });
+        }
+        Ok((scene, camera))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Metal};
+    use crate::ray::Ray;
+    use crate::sampler::IndependentSampler;
+
+    fn builder() -> SceneBuilder {
+        SceneBuilder::new()
+            .material("paint", Lambertian::new(Color::white()))
+            .sphere("left", Vec3::new(-1.0, 0.0, 0.0), 0.5, "paint")
+            .sphere("right", Vec3::new(1.0, 0.0, 0.0), 0.5, "paint")
+            .quad_light("lamp", Vec3::new(0.0, 0.0, 2.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Color::white())
+            .look_at(Vec3::new(0.0, -5.0, 0.0), Vec3::new(0.0, 0.0, 0.0), 40.0, 1.0)
+    }
+
+    #[test]
+    fn test_shared_materials() {
+        let (scene, _) = builder().build().unwrap();
+        assert_eq!(scene.names["right"], 1);
+        // Both spheres share the paint, the lamp has its own material.
+        assert_eq!(scene.material_numbers(), [1, 1, 2]);
+        let left = scene.object("left").unwrap().material().unwrap();
+        let right = scene.object("right").unwrap().material().unwrap();
+        assert_eq!(left.identity(), right.identity());
+
+        // Registering the paint again changes it for both spheres.
+        let builder = builder().material("paint", Metal::new(Color::white(), 0.0));
+        let paint = builder.material_ref("paint").unwrap();
+        let (scene, _) = builder.build().unwrap();
+        let left = scene.object("left").unwrap().material().unwrap();
+        assert_eq!(left.identity(), paint.identity());
+    }
+    #[test]
+    fn test_ground_plane() {
+        let ground = builder().plane("ground", Vec3::new_zero(), Vec3::new(0.0, 0.0, -1.0), "paint");
+        let (scene, _) = ground.build().unwrap();
+        let ground = scene.object("ground").unwrap();
+        let ray = Ray::new(Vec3::new(0.0, -1.0, 1.0), Vec3::new(0.0, 1.0, -1.0));
+        let hit = ground.hit(&ray, 0.001, f32::MAX).unwrap();
+        assert!(hit.front_face && hit.normal.z == 1.0);
+        // Light scatters back up from the ground.
+        let mut sampler = IndependentSampler::new(0);
+        for _ in 0..100 {
+            let scattered = hit.material.scatter(&ray, &hit, &mut sampler).unwrap().ray.unwrap();
+            assert!(scattered.direction.z > 0.0);
+        }
+        assert!(ground.hit(&Ray::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0)), 0.001, f32::MAX).is_none());
+    }
+    #[test]
+    fn test_build_errors() {
+        assert!(builder().sphere("left", Vec3::new_zero(), 1.0, "paint").build().is_err());
+        assert!(builder().sphere("extra", Vec3::new_zero(), 1.0, "gold").build().is_err());
+        assert!(SceneBuilder::new().build().is_err());
+    }
+}
@@ -1,43 +1,146 @@
-use rand::prelude::*;
 use std::f32::consts::PI;
 
 use crate::vec3::Vec3;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 
+/// Projection of the scene onto the image.
+pub trait Camera {
+    /// Ray through the point `(u, v)` of the image, from `(0, 0)` at the
+    /// bottom left to `(1, 1)` at the top right.
+    fn get_ray(&self, u: f32, v: f32, sampler: &mut dyn Sampler) -> Ray;
+
+    /// `get_ray` with the part of the light along the ray that reaches the
+    /// image, 0 where the lens shows nothing and less than 1 where it
+    /// vignettes.
+    fn get_weighted_ray(&self, u: f32, v: f32, sampler: &mut dyn Sampler) -> (Ray, f32) {
+        (self.get_ray(u, v, sampler), 1.0)
+    }
+
+    /// Part `(u0, v0, u1, v1)` of the image showing the same view as the
+    /// point `(u, v)`, which the reconstruction filter keeps its samples
+    /// within: the whole image but for stereo cameras.
+    fn view(&self, _u: f32, _v: f32) -> (f32, f32, f32, f32) {
+        (0.0, 0.0, 1.0, 1.0)
+    }
+}
+
+impl<C: Camera + ?Sized> Camera for Box<C> {
+    fn get_ray(&self, u: f32, v: f32, sampler: &mut dyn Sampler) -> Ray {
+        (**self).get_ray(u, v, sampler)
+    }
+
+    fn get_weighted_ray(&self, u: f32, v: f32, sampler: &mut dyn Sampler) -> (Ray, f32) {
+        (**self).get_weighted_ray(u, v, sampler)
+    }
+
+    fn view(&self, u: f32, v: f32) -> (f32, f32, f32, f32) {
+        (**self).view(u, v)
+    }
+}
+
+/// Exposure in stops of a photograph taken at `f_number` for `shutter`
+/// seconds at `iso`, relative to the sunny 16 rule (f/16 for 1/100 s at ISO
+/// 100), which suits daylight scenes with radiances around 1.
+pub fn exposure(f_number: f32, shutter: f32, iso: f32) -> f32 {
+    let exposure_value = (f_number * f_number / shutter * 100.0 / iso).log2();
+    (16.0f32 * 16.0 * 100.0).log2() - exposure_value
+}
+
+/// Right, up and backward unit vectors of a camera at `look_from` looking
+/// at `look_at`, `view_up` pointing up in the image.
+fn basis(look_from: Vec3, look_at: Vec3, view_up: Vec3) -> (Vec3, Vec3, Vec3) {
+    let backward = (look_from - look_at).make_unit_vector();
+    let right = view_up.cross(backward).make_unit_vector();
+    (right, backward.cross(right), backward)
+}
+
+/// Shape of the aperture of a lens, and how much light it lets through
+/// away from the centre of the image.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Aperture {
+    /// Straight blades closing the aperture into a regular polygon, which
+    /// shapes out of focus highlights; below 3 it stays round.
+    pub blades: u32,
+    /// Rotation of the polygon, in degrees.
+    pub rotation: f32,
+    /// Natural darkening of the corners, from 0 for none to 1 for the full
+    /// cos⁴ falloff.
+    pub vignetting: f32,
+    /// Shift of the front of the lens barrel across the aperture at the
+    /// corners of the image, in diameters of the aperture, which clips the
+    /// out of focus highlights there into cat's eyes: from 0 for none to 1
+    /// for corners left dark.
+    pub cat_eye: f32,
+}
+
+/// Lens and sensor of a real camera, in millimetres. Scenes are in metres.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Lens {
+    pub focal_length: f32,
+    /// Width and height of the sensor; the image is the largest part of it
+    /// with the aspect ratio of the image.
+    pub sensor: (f32, f32),
+    /// Focal length over the diameter of the aperture.
+    pub f_number: f32,
+}
+
+/// Thin lens perspective camera.
 #[derive(Copy, Clone)]
-pub struct Camera {
+pub struct PerspectiveCamera {
     pub origin: Vec3,
     pub lower_left_corner: Vec3,
     pub horizontal: Vec3,
     pub vertical: Vec3,
     pub u: Vec3,
     pub v: Vec3,
-    pub w: Vec3,
     pub lens_radius: f32,
+    pub aperture: Aperture,
 }
 
-fn random_in_unit_disk() -> Vec3 {
-    let mut rng = thread_rng();
-    loop {
-        let p = 2.0 * Vec3::new(rng.gen_range(0.0, 1.0), rng.gen_range(0.0, 1.0), 0.0) - Vec3::new(1.0, 1.0, 0.0);
-        if p.dot(p) < 1.0 {
-            return p;
-        }
+/// Point of the unit disk from a point of the unit square, by Shirley's
+/// concentric mapping, which keeps the strata of the square compact.
+fn concentric_sample_disk(u: (f32, f32)) -> Vec3 {
+    let (x, y) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+    if x == 0.0 && y == 0.0 {
+        return Vec3::new_zero();
     }
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, PI / 4.0 * (y / x))
+    } else {
+        (y, PI / 2.0 - PI / 4.0 * (x / y))
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
+
+/// Point of the regular polygon with `sides` corners on the unit circle,
+/// the first at `rotation` degrees, from a point of the unit square.
+fn sample_polygon(u: (f32, f32), sides: u32, rotation: f32) -> Vec3 {
+    // A triangle between the centre and two corners, then a point of it.
+    let side = (u.0 * sides as f32).min(sides as f32 - 1.0);
+    let corner = |index: f32| {
+        let angle = rotation.to_radians() + 2.0 * PI * index / sides as f32;
+        Vec3::new(angle.cos(), angle.sin(), 0.0)
+    };
+    let (first, second) = (corner(side.floor()), corner(side.floor() + 1.0));
+    let along = side.fract();
+    u.1.sqrt() * ((1.0 - along) * first + along * second)
 }
 
-impl Camera {
+impl PerspectiveCamera {
+    /// Camera at `look_from` looking at `look_at`, `view_up` pointing up in
+    /// the image, seeing `vertical_fov` degrees from the bottom to the top
+    /// of an image `aspect` times wider than high, through a lens of
+    /// diameter `aperture` focused at `focus_dist`.
     pub fn new(
         look_from: Vec3, look_at: Vec3, view_up: Vec3, 
         vertical_fov: f32, aspect: f32, aperture: f32, focus_dist: f32
-    ) -> Camera {
+    ) -> PerspectiveCamera {
         let theta = vertical_fov * PI / 180.0;
         let half_height = (theta / 2.0).tan();
         let half_width = aspect * half_height;
-        let camera_direction = (look_from - look_at).make_unit_vector();
-        let u = view_up.cross(camera_direction).make_unit_vector();
-        let v = camera_direction.cross(u);
-        Camera {
+        let (u, v, camera_direction) = basis(look_from, look_at, view_up);
+        PerspectiveCamera {
             origin : look_from,
             lower_left_corner: look_from - focus_dist * (half_width * u + half_height * v + camera_direction),
             horizontal: 2.0 * half_width * focus_dist * u,
@@ -45,15 +148,441 @@ impl Camera {
             lens_radius: aperture / 2.0,
             u,
             v,
-            w: camera_direction,
+            aperture: Aperture::default(),
+        }
+    }
+
+    /// Camera at `look_from` looking at `look_at`, `view_up` pointing up in
+    /// the image, through `lens` focused at `focus_dist`, for an image
+    /// `aspect` times wider than high.
+    pub fn physical(
+        look_from: Vec3, look_at: Vec3, view_up: Vec3, aspect: f32, focus_dist: f32, lens: &Lens,
+    ) -> PerspectiveCamera {
+        let (width, height) = lens.sensor;
+        let height = if aspect >= width / height { width / aspect } else { height };
+        let vertical_fov = 2.0 * (height / (2.0 * lens.focal_length)).atan() * 180.0 / PI;
+        // Millimetres to the metres of the scene.
+        let aperture = lens.focal_length / lens.f_number / 1000.0;
+        PerspectiveCamera::new(look_from, look_at, view_up, vertical_fov, aspect, aperture, focus_dist)
+    }
+
+    /// Centre of the image plane, at the focus distance along the axis.
+    fn image_center(&self) -> Vec3 {
+        self.lower_left_corner + 0.5 * self.horizontal + 0.5 * self.vertical
+    }
+
+    /// The eye `offset` to the right of the camera with a parallel axis,
+    /// its image plane shifted so that both meet `convergence` in front.
+    pub fn parallel_eye(&self, offset: f32, convergence: f32) -> PerspectiveCamera {
+        let focus_dist = (self.image_center() - self.origin).length();
+        PerspectiveCamera {
+            origin: self.origin + offset * self.u,
+            lower_left_corner: self.lower_left_corner + offset * (1.0 - focus_dist / convergence) * self.u,
+            ..*self
+        }
+    }
+
+    /// The eye `offset` to the right of the camera, turned towards the
+    /// point of the axis `convergence` in front.
+    pub fn toe_in_eye(&self, offset: f32, convergence: f32) -> PerspectiveCamera {
+        let axis = self.image_center() - self.origin;
+        let focus_dist = axis.length();
+        let origin = self.origin + offset * self.u;
+        let look_at = self.origin + convergence / focus_dist * axis;
+        let (u, v, backward) = basis(origin, look_at, self.v);
+        let horizontal = self.horizontal.length() * u;
+        let vertical = self.vertical.length() * v;
+        PerspectiveCamera {
+            origin,
+            lower_left_corner: origin - focus_dist * backward - 0.5 * horizontal - 0.5 * vertical,
+            horizontal,
+            vertical,
+            u,
+            v,
+            ..*self
         }
     }
-    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk();
+}
+
+impl Camera for PerspectiveCamera {
+    /// Ray from a point of the lens.
+    fn get_ray(&self, u: f32, v: f32, sampler: &mut dyn Sampler) -> Ray {
+        self.get_weighted_ray(u, v, sampler).0
+    }
+
+    fn get_weighted_ray(&self, u: f32, v: f32, sampler: &mut dyn Sampler) -> (Ray, f32) {
+        let aperture = &self.aperture;
+        let lens = if aperture.blades >= 3 {
+            sample_polygon(sampler.get_2d(), aperture.blades, aperture.rotation)
+        } else {
+            concentric_sample_disk(sampler.get_2d())
+        };
+        let rd = self.lens_radius * lens;
         let offset = self.u * rd.x + self.v * rd.y;
+        let target = self.lower_left_corner + u * self.horizontal + v * self.vertical;
+        let ray = Ray::new(self.origin + offset, target - self.origin - offset);
+
+        let mut weight = 1.0;
+        if aperture.vignetting > 0.0 {
+            let cos_theta = (self.image_center() - self.origin).length() / (target - self.origin).length();
+            weight *= 1.0 - aperture.vignetting * (1.0 - cos_theta.powi(4));
+        }
+        if aperture.cat_eye > 0.0 {
+            // Where the front of the barrel lets light through, in radii of
+            // the aperture, shifted outwards with the distance from the
+            // centre of the image relative to the corners.
+            let (x, y) = ((u - 0.5) * self.horizontal.length(), (v - 0.5) * self.vertical.length());
+            let corner = 0.5 * (self.horizontal.square_length() + self.vertical.square_length()).sqrt();
+            let shift = 2.0 * aperture.cat_eye / corner * Vec3::new(x, y, 0.0);
+            if (lens - shift).length() > 1.0 {
+                weight = 0.0;
+            }
+        }
+        (ray, weight)
+    }
+}
+
+
+/// Parallel projection, keeping sizes whatever the distance, as in
+/// architectural elevations.
+#[derive(Copy, Clone)]
+pub struct OrthographicCamera {
+    pub lower_left_corner: Vec3,
+    pub horizontal: Vec3,
+    pub vertical: Vec3,
+    pub direction: Vec3,
+}
+
+impl OrthographicCamera {
+    /// Camera looking from the plane through `look_from` towards `look_at`,
+    /// `view_up` pointing up in the image, seeing `height` units from the
+    /// bottom to the top of an image `aspect` times wider than high.
+    pub fn new(look_from: Vec3, look_at: Vec3, view_up: Vec3, height: f32, aspect: f32) -> OrthographicCamera {
+        let (right, up, backward) = basis(look_from, look_at, view_up);
+        let (horizontal, vertical) = (aspect * height * right, height * up);
+        OrthographicCamera {
+            lower_left_corner: look_from - 0.5 * horizontal - 0.5 * vertical,
+            horizontal,
+            vertical,
+            direction: -backward,
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, u: f32, v: f32, _sampler: &mut dyn Sampler) -> Ray {
+        Ray::new(self.lower_left_corner + u * self.horizontal + v * self.vertical, self.direction)
+    }
+}
+
+/// Equidistant fisheye lens, the angle from the centre of the image growing
+/// with the distance to it. Its image circle fits the height of the image,
+/// and the image is black outside of it.
+#[derive(Copy, Clone)]
+pub struct FisheyeCamera {
+    pub origin: Vec3,
+    pub right: Vec3,
+    pub up: Vec3,
+    pub forward: Vec3,
+    /// Angle from the bottom to the top of the image, in radians.
+    pub field_of_view: f32,
+    pub aspect: f32,
+}
+
+impl FisheyeCamera {
+    /// Camera at `look_from` looking at `look_at`, `view_up` pointing up in
+    /// the image, seeing `field_of_view` degrees from the bottom to the top
+    /// of an image `aspect` times wider than high; `180.0` sees the whole
+    /// half space in front of the camera within the height of the image.
+    pub fn new(look_from: Vec3, look_at: Vec3, view_up: Vec3, field_of_view: f32, aspect: f32) -> FisheyeCamera {
+        let (right, up, backward) = basis(look_from, look_at, view_up);
+        FisheyeCamera {
+            origin: look_from,
+            right,
+            up,
+            forward: -backward,
+            field_of_view: field_of_view * PI / 180.0,
+            aspect,
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, u: f32, v: f32, _sampler: &mut dyn Sampler) -> Ray {
+        let (x, y) = ((u - 0.5) * self.aspect, v - 0.5);
+        let r = (x * x + y * y).sqrt();
+        if r == 0.0 {
+            return Ray::new(self.origin, self.forward);
+        }
+        let theta = r * self.field_of_view;
+        let side = (x / r) * self.right + (y / r) * self.up;
+        Ray::new(self.origin, theta.sin() * side + theta.cos() * self.forward)
+    }
+
+    /// Rays from outside the image circle do not reach the image.
+    fn get_weighted_ray(&self, u: f32, v: f32, sampler: &mut dyn Sampler) -> (Ray, f32) {
+        let (x, y) = ((u - 0.5) * self.aspect, v - 0.5);
+        let weight = if x * x + y * y > 0.25 { 0.0 } else { 1.0 };
+        (self.get_ray(u, v, sampler), weight)
+    }
+}
+
+/// Full panorama in equirectangular projection: the longitude runs around
+/// the width of the image, from behind the camera on the left to behind it
+/// on the right, and the latitude from straight down at the bottom to
+/// straight up at the top. Images should be twice as wide as high.
+#[derive(Copy, Clone)]
+pub struct EquirectangularCamera {
+    pub origin: Vec3,
+    pub right: Vec3,
+    pub up: Vec3,
+    pub forward: Vec3,
+    /// Distance of the eye to the right of the origin, whichever way it
+    /// looks, for omni-directional stereo; 0 for a single viewpoint. It
+    /// shrinks with the cosine of the latitude, down to 0 at the poles,
+    /// where the eyes could not agree on which way is right.
+    pub eye_offset: f32,
+}
+
+impl EquirectangularCamera {
+    /// Camera at `look_from` with `look_at` in the centre of the image and
+    /// `view_up` towards its top.
+    pub fn new(look_from: Vec3, look_at: Vec3, view_up: Vec3) -> EquirectangularCamera {
+        let (right, up, backward) = basis(look_from, look_at, view_up);
+        EquirectangularCamera { origin: look_from, right, up, forward: -backward, eye_offset: 0.0 }
+    }
+
+    /// The eye `offset` to the right of the camera, turning with the
+    /// direction it looks in.
+    pub fn eye(&self, offset: f32) -> EquirectangularCamera {
+        EquirectangularCamera { eye_offset: offset, ..*self }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, u: f32, v: f32, _sampler: &mut dyn Sampler) -> Ray {
+        let longitude = 2.0 * PI * (u - 0.5);
+        let latitude = PI * (v - 0.5);
+        let horizontal = longitude.sin() * self.right + longitude.cos() * self.forward;
+        let eye_right = longitude.cos() * self.right - longitude.sin() * self.forward;
         Ray::new(
-            self.origin + offset,
-            self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin - offset
+            self.origin + self.eye_offset * latitude.cos() * eye_right,
+            latitude.cos() * horizontal + latitude.sin() * self.up,
         )
     }
-}
\ No newline at end of file
+}
+
+/// How the views of the two eyes share a single image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StereoLayout {
+    /// Left eye in the left half, right eye in the right half.
+    SideBySide,
+    /// Left eye in the top half, right eye in the bottom half.
+    OverUnder,
+}
+
+/// Left and right eye views packed into one image. Each half is a view of
+/// its own, so that the reconstruction filter does not blend the eyes
+/// along the seam.
+#[derive(Copy, Clone)]
+pub struct StereoCamera<C> {
+    pub left: C,
+    pub right: C,
+    pub layout: StereoLayout,
+}
+
+impl<C> StereoCamera<C> {
+    pub fn new(left: C, right: C, layout: StereoLayout) -> StereoCamera<C> {
+        StereoCamera { left, right, layout }
+    }
+}
+
+impl StereoCamera<PerspectiveCamera> {
+    /// Eyes `interocular` apart on either side of `camera`, looking the
+    /// same way, with zero parallax at `convergence`. The image of each eye
+    /// keeps the aspect ratio of `camera`.
+    pub fn parallel(camera: &PerspectiveCamera, interocular: f32, convergence: f32, layout: StereoLayout) -> Self {
+        let offset = 0.5 * interocular;
+        StereoCamera::new(camera.parallel_eye(-offset, convergence), camera.parallel_eye(offset, convergence), layout)
+    }
+
+    /// Eyes `interocular` apart on either side of `camera`, both turned
+    /// towards the point `convergence` in front of it, which adds vertical
+    /// parallax in the corners.
+    pub fn toe_in(camera: &PerspectiveCamera, interocular: f32, convergence: f32, layout: StereoLayout) -> Self {
+        let offset = 0.5 * interocular;
+        StereoCamera::new(camera.toe_in_eye(-offset, convergence), camera.toe_in_eye(offset, convergence), layout)
+    }
+}
+
+impl StereoCamera<EquirectangularCamera> {
+    /// Omni-directional stereo panorama, with the eyes `interocular` apart
+    /// whichever way they look, closing in towards the poles, packed
+    /// over/under into a square image.
+    pub fn omnidirectional(camera: &EquirectangularCamera, interocular: f32) -> Self {
+        let offset = 0.5 * interocular;
+        StereoCamera::new(camera.eye(-offset), camera.eye(offset), StereoLayout::OverUnder)
+    }
+}
+
+impl<C: Camera> Camera for StereoCamera<C> {
+    fn get_ray(&self, u: f32, v: f32, sampler: &mut dyn Sampler) -> Ray {
+        self.get_weighted_ray(u, v, sampler).0
+    }
+
+    fn get_weighted_ray(&self, u: f32, v: f32, sampler: &mut dyn Sampler) -> (Ray, f32) {
+        match self.layout {
+            StereoLayout::SideBySide if u < 0.5 => self.left.get_weighted_ray(2.0 * u, v, sampler),
+            StereoLayout::SideBySide => self.right.get_weighted_ray(2.0 * u - 1.0, v, sampler),
+            StereoLayout::OverUnder if v >= 0.5 => self.left.get_weighted_ray(u, 2.0 * v - 1.0, sampler),
+            StereoLayout::OverUnder => self.right.get_weighted_ray(u, 2.0 * v, sampler),
+        }
+    }
+
+    fn view(&self, u: f32, v: f32) -> (f32, f32, f32, f32) {
+        match self.layout {
+            StereoLayout::SideBySide if u < 0.5 => (0.0, 0.0, 0.5, 1.0),
+            StereoLayout::SideBySide => (0.5, 0.0, 1.0, 1.0),
+            StereoLayout::OverUnder if v >= 0.5 => (0.0, 0.5, 1.0, 1.0),
+            StereoLayout::OverUnder => (0.0, 0.0, 1.0, 0.5),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::create_sampler;
+    use crate::settings::SamplerKind;
+
+    fn assert_direction(ray: &Ray, expected: Vec3) {
+        let direction = ray.direction.make_unit_vector();
+        assert!((direction - expected).length() < 1e-4, "{} is not {}", direction, expected);
+    }
+
+    #[test]
+    fn test_cameras() {
+        let mut sampler = create_sampler(SamplerKind::Independent, 1, 0);
+        let sampler = sampler.as_mut();
+        let (from, at, up) = (Vec3::new(0.0, -5.0, 0.0), Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        let (forward, right) = (Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        let perspective = PerspectiveCamera::new(from, at, up, 90.0, 2.0, 0.0, 5.0);
+        assert_direction(&perspective.get_ray(0.5, 0.5, sampler), forward);
+        assert_direction(&perspective.get_ray(0.5, 1.0, sampler), (forward + up).make_unit_vector());
+
+        // Parallel rays, from points `height` apart.
+        let orthographic = OrthographicCamera::new(from, at, up, 4.0, 2.0);
+        let (bottom, top) = (orthographic.get_ray(0.25, 0.0, sampler), orthographic.get_ray(0.25, 1.0, sampler));
+        assert_direction(&bottom, forward);
+        assert_direction(&top, forward);
+        assert!((top.origin - bottom.origin - 4.0 * up).length() < 1e-5);
+        assert!((bottom.origin - Vec3::new(-2.0, -5.0, -2.0)).length() < 1e-5);
+
+        // Angles grow linearly from the centre, here to 90° at the top.
+        let fisheye = FisheyeCamera::new(from, at, up, 180.0, 1.0);
+        assert_direction(&fisheye.get_ray(0.5, 0.5, sampler), forward);
+        assert_direction(&fisheye.get_ray(0.5, 1.0, sampler), up);
+        assert_direction(&fisheye.get_ray(0.5, 0.75, sampler), (forward + up).make_unit_vector());
+        assert_direction(&fisheye.get_ray(1.0, 0.5, sampler), right);
+        assert_eq!(fisheye.get_weighted_ray(1.0, 0.5, sampler).1, 1.0);
+        // The corners are outside the image circle.
+        assert_eq!(fisheye.get_weighted_ray(0.95, 0.95, sampler).1, 0.0);
+
+        let panorama = EquirectangularCamera::new(from, at, up);
+        assert_direction(&panorama.get_ray(0.5, 0.5, sampler), forward);
+        assert_direction(&panorama.get_ray(0.75, 0.5, sampler), right);
+        assert_direction(&panorama.get_ray(0.0, 0.5, sampler), -forward);
+        assert_direction(&panorama.get_ray(0.3, 1.0, sampler), up);
+    }
+    #[test]
+    fn test_stereo_cameras() {
+        let mut sampler = create_sampler(SamplerKind::Independent, 1, 0);
+        let sampler = sampler.as_mut();
+        let (from, at, up) = (Vec3::new(0.0, -5.0, 0.0), Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        let (forward, right) = (Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let camera = PerspectiveCamera::new(from, at, up, 40.0, 1.5, 0.0, 5.0);
+        // The centres of both views meet at the convergence distance.
+        let converged = from + 8.0 * forward;
+        let meets = |ray: &Ray| {
+            let t = (converged - ray.origin).dot(forward) / ray.direction.dot(forward);
+            (ray.point_at_t(t) - converged).length() < 1e-4
+        };
+        for layout in [StereoLayout::SideBySide, StereoLayout::OverUnder] {
+            let parallel = StereoCamera::parallel(&camera, 0.2, 8.0, layout);
+            let toe_in = StereoCamera::toe_in(&camera, 0.2, 8.0, layout);
+            let ((left_u, left_v), (right_u, right_v)) = match layout {
+                StereoLayout::SideBySide => ((0.25, 0.5), (0.75, 0.5)),
+                StereoLayout::OverUnder => ((0.5, 0.75), (0.5, 0.25)),
+            };
+            for stereo in [parallel, toe_in] {
+                let left = stereo.get_ray(left_u, left_v, sampler);
+                let right_eye = stereo.get_ray(right_u, right_v, sampler);
+                assert!((left.origin - (from - 0.1 * right)).length() < 1e-5);
+                assert!((right_eye.origin - (from + 0.1 * right)).length() < 1e-5);
+                assert!(meets(&left) && meets(&right_eye));
+            }
+            // Only toe-in turns the image planes.
+            assert!((parallel.left.horizontal - parallel.right.horizontal).length() < 1e-5);
+            assert!((toe_in.left.horizontal - toe_in.right.horizontal).length() > 1e-3);
+        }
+
+        // Left eye on top, on the left of the direction it looks in.
+        let ods = StereoCamera::omnidirectional(&EquirectangularCamera::new(from, at, up), 0.2);
+        let left = ods.get_ray(0.5, 0.75, sampler);
+        assert!((left.origin - (from - 0.1 * right)).length() < 1e-5);
+        assert_direction(&left, forward);
+        let looking_right = ods.get_ray(0.75, 0.25, sampler);
+        assert!((looking_right.origin - (from - 0.1 * forward)).length() < 1e-5);
+        assert_direction(&looking_right, right);
+        // The eyes meet at the poles.
+        assert!((ods.get_ray(0.3, 1.0, sampler).origin - from).length() < 1e-5);
+        assert!((ods.get_ray(0.3, 0.5, sampler).origin - from).length() < 1e-5);
+        // Each eye is a view of its own.
+        assert_eq!(ods.view(0.3, 0.75), (0.0, 0.5, 1.0, 1.0));
+        assert_eq!(ods.view(0.3, 0.25), (0.0, 0.0, 1.0, 0.5));
+    }
+    #[test]
+    fn test_physical_camera() {
+        let mut sampler = create_sampler(SamplerKind::Independent, 1, 0);
+        let sampler = sampler.as_mut();
+        let (from, at, up) = (Vec3::new(0.0, -5.0, 0.0), Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        // A 50 mm lens sees 24 mm of a 36x24 mm sensor over 50 mm, and 18
+        // mm over 50 mm of a sensor wider than the image.
+        let lens = Lens { focal_length: 50.0, sensor: (36.0, 24.0), f_number: 2.0 };
+        let camera = PerspectiveCamera::physical(from, at, up, 1.5, 5.0, &lens);
+        assert!((camera.vertical.length() - 5.0 * 24.0 / 50.0).abs() < 1e-4);
+        assert!((camera.horizontal.length() - 5.0 * 36.0 / 50.0).abs() < 1e-4);
+        assert!((camera.lens_radius - 0.0125).abs() < 1e-6);
+        let square = PerspectiveCamera::physical(from, at, up, 2.0, 5.0, &lens);
+        assert!((square.vertical.length() - 5.0 * 18.0 / 50.0).abs() < 1e-4);
+
+        // Rays leave from the hexagon, whose corners are on the lens circle.
+        let hexagon = PerspectiveCamera { aperture: Aperture { blades: 6, rotation: 30.0, ..Aperture::default() }, ..camera };
+        let apothem = camera.lens_radius * (PI / 6.0).cos();
+        let mut widest: f32 = 0.0;
+        for _ in 0..1000 {
+            let offset = hexagon.get_ray(0.5, 0.5, sampler).origin - from;
+            assert!(offset.x.abs() <= apothem + 1e-6 && offset.length() <= camera.lens_radius + 1e-6);
+            widest = widest.max(offset.z.abs());
+        }
+        assert!(widest > 0.95 * camera.lens_radius);
+
+        // cos⁴ falloff, and barrel clipping growing towards the corners.
+        let vignetted = PerspectiveCamera { aperture: Aperture { vignetting: 1.0, ..Aperture::default() }, ..camera };
+        assert_eq!(vignetted.get_weighted_ray(0.5, 0.5, sampler).1, 1.0);
+        let cos_corner = 50.0 / (50.0f32 * 50.0 + 18.0 * 18.0 + 12.0 * 12.0).sqrt();
+        assert!((vignetted.get_weighted_ray(1.0, 1.0, sampler).1 - cos_corner.powi(4)).abs() < 1e-4);
+        let cat_eye = PerspectiveCamera { aperture: Aperture { cat_eye: 0.5, ..Aperture::default() }, ..camera };
+        let passing = |u: f32, v: f32, sampler: &mut dyn Sampler| {
+            (0..1000).filter(|_| cat_eye.get_weighted_ray(u, v, sampler).1 > 0.0).count()
+        };
+        assert_eq!(passing(0.5, 0.5, sampler), 1000);
+        let corner = passing(1.0, 0.0, sampler);
+        assert!(corner > 300 && corner < 500, "{}", corner);
+
+        assert!(exposure(16.0, 0.01, 100.0).abs() < 1e-5);
+        assert!((exposure(8.0, 0.01, 100.0) - 2.0).abs() < 1e-5);
+        assert!((exposure(16.0, 0.005, 400.0) - 1.0).abs() < 1e-5);
+    }
+}
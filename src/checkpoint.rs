@@ -0,0 +1,142 @@
+use std::fs;
+use std::io;
+
+use crate::settings::RenderSettings;
+use crate::RenderState;
+
+const MAGIC: &[u8; 8] = b"RTCKPT01";
+
+/// Reads back what the `encode` methods of the accumulated buffers wrote.
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Decoder<'a> {
+        Decoder { bytes }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < length {
+            return Err(String::from("truncated checkpoint"));
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Exactly the `f32` written, NaNs included.
+    pub fn f32(&mut self) -> Result<f32, String> {
+        self.u32().map(f32::from_bits)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.u32()? as usize;
+        let bytes = self.take(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| String::from("invalid checkpoint"))
+    }
+}
+
+pub fn put_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn put_f32(bytes: &mut Vec<u8>, value: f32) {
+    put_u32(bytes, value.to_bits());
+}
+
+/// The settings that decide which samples are taken and how they add up,
+/// which a render must share with the checkpoint it resumes; the
+/// post-processing may differ, unless adaptive sampling measures the error
+/// through it.
+pub fn fingerprint(settings: &RenderSettings) -> String {
+    let lens = (
+        settings.focal_length, settings.sensor, settings.f_number,
+        settings.blades, settings.blade_rotation, settings.vignetting, settings.cat_eye,
+    );
+    let camera = (settings.camera, settings.stereo, settings.interocular, settings.convergence, lens);
+    // The passes chosen for a time limit are left out, so that the render
+    // can be resumed with another limit or none.
+    let samples = (settings.s_size, settings.adaptive_threshold, settings.min_samples, settings.pass_samples);
+    let display = settings.adaptive_threshold.map(|_| {
+        (settings.exposure, settings.tone_map, settings.white_point, settings.shutter, settings.iso)
+    });
+    format!("{:?}", (
+        settings.x_size, settings.y_size, samples, settings.spectral, settings.scene, camera,
+        settings.sampler, settings.filter, settings.filter_radius, settings.seed, !settings.aovs.is_empty() || settings.denoise,
+        display,
+    ))
+}
+
+/// The state of a render after a pass, exactly, with the settings it was
+/// rendered with.
+pub fn encode(settings: &RenderSettings, state: &RenderState) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    let fingerprint = fingerprint(settings);
+    put_u32(&mut bytes, fingerprint.len() as u32);
+    bytes.extend_from_slice(fingerprint.as_bytes());
+    state.encode(&mut bytes);
+    bytes
+}
+
+/// State encoded by `encode` for a render with the same settings.
+pub fn decode(settings: &RenderSettings, bytes: &[u8]) -> Result<RenderState, String> {
+    let mut decoder = Decoder::new(bytes);
+    if decoder.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(String::from("not a checkpoint"));
+    }
+    if decoder.string()? != fingerprint(settings) {
+        return Err(String::from("the checkpoint was rendered with other settings"));
+    }
+    let state = RenderState::decode(settings, &mut decoder)?;
+    if !decoder.bytes.is_empty() {
+        return Err(String::from("invalid checkpoint"));
+    }
+    Ok(state)
+}
+
+/// Writes the checkpoint at once, so that an interruption leaves the
+/// previous one whole.
+pub fn save(path: &str, settings: &RenderSettings, state: &RenderState) -> io::Result<()> {
+    let partial = format!("{}.partial", path);
+    fs::write(&partial, encode(settings, state))?;
+    fs::rename(&partial, path)
+}
+
+pub fn load(path: &str, settings: &RenderSettings) -> io::Result<RenderState> {
+    let bytes = fs::read(path)?;
+    decode(settings, &bytes).map_err(|message| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, message)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SceneSource;
+
+    #[test]
+    fn test_decode_errors() {
+        let settings = RenderSettings { x_size: 4, y_size: 2, s_size: 2, threads: 1, ..RenderSettings::default() };
+        let bytes = encode(&settings, &RenderState::new(&settings, SceneSource::Settings(fingerprint(&settings))));
+        assert!(decode(&settings, &bytes).is_ok());
+        assert!(decode(&settings, &bytes[..bytes.len() - 1]).is_err());
+        assert!(decode(&settings, &[bytes.as_slice(), &[0]].concat()).is_err());
+        assert!(decode(&settings, b"P3\n4 2\n255\n").is_err());
+        // Only the post-processing may change.
+        let other_seed = RenderSettings { seed: 1, ..settings.clone() };
+        assert!(decode(&other_seed, &bytes).is_err());
+        let brighter = RenderSettings { exposure: 1.0, ..settings.clone() };
+        assert!(decode(&brighter, &bytes).is_ok());
+        let time_limited = RenderSettings { time_limit: Some(60.0), ..settings.clone() };
+        assert!(decode(&time_limited, &bytes).is_ok());
+        // Adaptive sampling stops pixels by their error on the display.
+        let adaptive = RenderSettings { adaptive_threshold: Some(0.02), ..settings.clone() };
+        let bytes = encode(&adaptive, &RenderState::new(&adaptive, SceneSource::Settings(fingerprint(&adaptive))));
+        assert!(decode(&RenderSettings { dither: true, ..adaptive.clone() }, &bytes).is_ok());
+        assert!(decode(&RenderSettings { exposure: 1.0, ..adaptive }, &bytes).is_err());
+    }
+}
@@ -49,6 +49,11 @@ impl Color {
                green: self.green.sqrt(),
                blue: self.blue.sqrt() }
     }
+    pub fn exp(&self) -> Self {
+        Self { red: self.red.exp(),
+               green: self.green.exp(),
+               blue: self.blue.exp() }
+    }
 }
 
 impl fmt::Display for Color {
@@ -200,7 +205,7 @@ impl ops::DivAssign<f32> for Color {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct ColorU8 {
     pub red: u8,
     pub green: u8,
@@ -0,0 +1,63 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::settings::RenderSettings;
+
+/// Stops a render before it takes all its samples, when its time budget
+/// runs out or when it is cancelled. Clones share the cancellation, so that
+/// one can be handed to another thread, or to a signal handler, to cancel
+/// the render with.
+#[derive(Clone, Debug)]
+pub struct RenderController {
+    deadline: Option<Instant>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl RenderController {
+    /// Controller of a render of `settings` starting now. A time limit too
+    /// long for the clock to reach sets no deadline.
+    pub fn new(settings: &RenderSettings) -> RenderController {
+        let deadline = settings.time_limit
+            .and_then(|seconds| Duration::try_from_secs_f32(seconds).ok())
+            .and_then(|limit| Instant::now().checked_add(limit));
+        RenderController {
+            deadline,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Whether the render should take no more samples.
+    pub fn should_stop(&self) -> bool {
+        self.is_cancelled() || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_controller() {
+        let controller = RenderController::new(&RenderSettings::default());
+        assert!(!controller.should_stop());
+        let handle = controller.clone();
+        handle.cancel();
+        assert!(controller.is_cancelled() && controller.should_stop());
+
+        let limited = RenderController::new(&RenderSettings { time_limit: Some(0.05), ..RenderSettings::default() });
+        assert!(!limited.should_stop());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(limited.should_stop() && !limited.is_cancelled());
+        let endless = RenderController::new(&RenderSettings { time_limit: Some(1e30), ..RenderSettings::default() });
+        assert!(!endless.should_stop());
+    }
+}
@@ -0,0 +1,185 @@
+use crate::aov::AovFilm;
+use crate::color::Color;
+use crate::film::Film;
+use crate::vec3::Vec3;
+
+/// Half side of the window of pixels averaged together.
+const WINDOW_RADIUS: i32 = 5;
+/// Half side of the patches compared to weight a pixel of the window.
+const PATCH_RADIUS: i32 = 1;
+/// Sensitivity of the colour weight to differences beyond the noise, and
+/// how much of the variance is taken as noise (Rousselle et al. 2012, who
+/// use 4; less keeps more of the detail of reflections).
+const K: f32 = 0.45;
+const ALPHA: f32 = 0.5;
+/// Spread of the feature weights, in squared differences.
+const NORMAL_SIGMA2: f32 = 0.05;
+const ALBEDO_SIGMA2: f32 = 0.01;
+/// Relative spread of the depth weight.
+const DEPTH_SIGMA2: f32 = 0.01;
+/// Added to the albedo before dividing by it.
+const ALBEDO_EPSILON: f32 = 1e-3;
+
+/// Per-pixel buffers guiding the denoiser, laid out like a film: row by
+/// row from the bottom.
+struct Guides {
+    albedo: Vec<Color>,
+    normal: Vec<Vec3>,
+    depth: Vec<f32>,
+}
+
+fn luminance(color: Color) -> f32 {
+    0.2126 * color.red + 0.7152 * color.green + 0.0722 * color.blue
+}
+
+fn channels(color: Color) -> [f32; 3] {
+    [color.red, color.green, color.blue]
+}
+
+/// Image of `film` denoised by non-local means guided by the albedo,
+/// normal and depth of `aovs`, along the lines of Rousselle et al. 2013,
+/// "Robust Denoising using Feature and Color Information". `variances`
+/// holds the variance of the luminance of each pixel, in the layout of the
+/// film. The colour is divided by the albedo before filtering, so that
+/// textures stay sharp and only the lighting is smoothed.
+pub fn denoise(film: &Film, aovs: &AovFilm, variances: &[f32]) -> Film {
+    let (width, height) = (film.width, film.height);
+    let size = (width * height) as usize;
+    let mut guides = Guides {
+        albedo: Vec::with_capacity(size),
+        normal: Vec::with_capacity(size),
+        depth: Vec::with_capacity(size),
+    };
+    let mut irradiance = Vec::with_capacity(size);
+    let mut irradiance_variance = Vec::with_capacity(size);
+    for j in film.y0..film.y0 + height {
+        for i in film.x0..film.x0 + width {
+            let albedo = aovs.albedo(i, j);
+            let divisor = Color::new(
+                albedo.red.max(0.0) + ALBEDO_EPSILON,
+                albedo.green.max(0.0) + ALBEDO_EPSILON,
+                albedo.blue.max(0.0) + ALBEDO_EPSILON,
+            );
+            let mut color = film.pixel(i, j);
+            color /= divisor;
+            irradiance.push(color);
+            let index = ((j - film.y0) * width + (i - film.x0)) as usize;
+            irradiance_variance.push(variances[index] / (luminance(divisor) * luminance(divisor)));
+            guides.albedo.push(albedo);
+            guides.normal.push(aovs.normal(i, j));
+            guides.depth.push(aovs.depth(i, j));
+        }
+    }
+
+    let mut denoised = Film::new(film.x0, film.y0, width, height, film.filter);
+    let (width, height) = (width as i32, height as i32);
+    let index = |x: i32, y: i32| (y * width + x) as usize;
+    let clamped = |x: i32, y: i32| index(x.clamp(0, width - 1), y.clamp(0, height - 1));
+    for y in 0..height {
+        for x in 0..width {
+            let p = index(x, y);
+            let mut sum = Color::black();
+            let mut weight_sum = 0.0;
+            for qy in (y - WINDOW_RADIUS).max(0)..=(y + WINDOW_RADIUS).min(height - 1) {
+                for qx in (x - WINDOW_RADIUS).max(0)..=(x + WINDOW_RADIUS).min(width - 1) {
+                    let q = index(qx, qy);
+                    // Distance between the patches around both pixels,
+                    // beyond what their noise explains.
+                    let mut distance = 0.0;
+                    for dy in -PATCH_RADIUS..=PATCH_RADIUS {
+                        for dx in -PATCH_RADIUS..=PATCH_RADIUS {
+                            let (a, b) = (clamped(x + dx, y + dy), clamped(qx + dx, qy + dy));
+                            let (variance_a, variance_b) = (irradiance_variance[a], irradiance_variance[b]);
+                            let noise = ALPHA * (variance_a + variance_a.min(variance_b));
+                            let scale = 1e-4 + K * K * (variance_a + variance_b);
+                            for (u, v) in channels(irradiance[a]).iter().zip(channels(irradiance[b]).iter()) {
+                                distance += ((u - v) * (u - v) - noise) / scale;
+                            }
+                        }
+                    }
+                    let patch_size = (2 * PATCH_RADIUS + 1) * (2 * PATCH_RADIUS + 1);
+                    let color_distance = (distance / (3 * patch_size) as f32).max(0.0);
+
+                    let normal = guides.normal[p] - guides.normal[q];
+                    let albedo = guides.albedo[p] - guides.albedo[q];
+                    let depth = guides.depth[p] - guides.depth[q];
+                    let depth_scale = DEPTH_SIGMA2 * (guides.depth[p] * guides.depth[p]).max(1e-4);
+                    let feature_distance = normal.square_length() / NORMAL_SIGMA2
+                        + albedo.square_length() / ALBEDO_SIGMA2
+                        + depth * depth / depth_scale;
+
+                    let weight = (-color_distance - feature_distance).exp();
+                    sum += weight * irradiance[q];
+                    weight_sum += weight;
+                }
+            }
+            let albedo = guides.albedo[p];
+            let mut color = sum / weight_sum;
+            color *= Color::new(
+                albedo.red.max(0.0) + ALBEDO_EPSILON,
+                albedo.green.max(0.0) + ALBEDO_EPSILON,
+                albedo.blue.max(0.0) + ALBEDO_EPSILON,
+            );
+            denoised.set_pixel(film.x0 + x as u32, film.y0 + y as u32, color);
+        }
+    }
+    denoised
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::{FirstHit, PathRecord};
+    use crate::film::Filter;
+    use crate::sampler::hash_float;
+    use crate::settings::FilterKind;
+
+    /// Noisy image of two walls meeting at `x = 8`, lit `1` and `0.25`,
+    /// with their AOVs.
+    fn noisy_walls() -> (Film, AovFilm, Vec<f32>) {
+        let filter = Filter::new(FilterKind::Box, 0.5);
+        let mut film = Film::new(0, 0, 16, 8, filter);
+        let mut aovs = AovFilm::new(16, 8, filter);
+        let mut variances = Vec::new();
+        for j in 0..8u32 {
+            for i in 0..16u32 {
+                let left = i < 8;
+                let light = if left { 1.0 } else { 0.25 };
+                let noise = 0.3 * (hash_float(&[u64::from(i), u64::from(j)]) - 0.5);
+                let (x, y) = (i as f32 + 0.5, j as f32 + 0.5);
+                film.add_sample(x, y, (0, 0, 16, 8), Color::white() * (light + noise));
+                let mut path = PathRecord::<Color>::new(&());
+                path.albedo = Color::white();
+                path.first_hit = Some(FirstHit {
+                    distance: 5.0,
+                    position: Vec3::new(i as f32, 0.0, j as f32),
+                    normal: if left { Vec3::new(1.0, 0.0, 0.0) } else { Vec3::new(0.0, 1.0, 0.0) },
+                    object: if left { 0 } else { 1 },
+                });
+                aovs.add_sample(x, y, (0, 0, 16, 8), &path);
+                variances.push(0.3 * 0.3 / 12.0);
+            }
+        }
+        (film, aovs, variances)
+    }
+
+    #[test]
+    fn test_denoise() {
+        let (film, aovs, variances) = noisy_walls();
+        let denoised = denoise(&film, &aovs, &variances);
+        let error = |film: &Film| {
+            let mut error = 0.0;
+            for j in 0..8 {
+                for i in 0..16 {
+                    let expected = if i < 8 { 1.0 } else { 0.25 };
+                    error += (film.pixel(i, j).green - expected).powi(2);
+                }
+            }
+            error
+        };
+        assert!(error(&denoised) < 0.5 * error(&film));
+        // The normals keep the walls apart.
+        assert!((denoised.pixel(7, 4).green - 1.0).abs() < 0.1);
+        assert!((denoised.pixel(8, 4).green - 0.25).abs() < 0.1);
+    }
+}
@@ -0,0 +1,114 @@
+use std::fs;
+use std::io;
+
+/// Channel of an image written to OpenEXR: its full name, such as `R` or
+/// `normal.X`, and its values row by row from the top.
+pub struct Channel<'a> {
+    pub name: String,
+    pub values: &'a [f32],
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// Uncompressed single-part scanline OpenEXR file of 32-bit float
+/// channels, each `width * height` values long.
+pub fn encode(width: u32, height: u32, channels: &[Channel]) -> Vec<u8> {
+    // Readers expect the channels sorted by name, in the list and in the
+    // scanlines alike.
+    let mut channels: Vec<&Channel> = channels.iter().collect();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&20_000_630i32.to_le_bytes());
+    bytes.extend_from_slice(&2i32.to_le_bytes());
+
+    let mut channel_list = Vec::new();
+    for channel in &channels {
+        channel_list.extend_from_slice(channel.name.as_bytes());
+        channel_list.push(0);
+        // Float pixels, not perceptually linear, no subsampling.
+        channel_list.extend_from_slice(&2i32.to_le_bytes());
+        channel_list.extend_from_slice(&[0, 0, 0, 0]);
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+    let mut window = Vec::new();
+    for value in &[0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+    write_attribute(&mut bytes, "channels", "chlist", &channel_list);
+    write_attribute(&mut bytes, "compression", "compression", &[0]);
+    write_attribute(&mut bytes, "dataWindow", "box2i", &window);
+    write_attribute(&mut bytes, "displayWindow", "box2i", &window);
+    write_attribute(&mut bytes, "lineOrder", "lineOrder", &[0]);
+    write_attribute(&mut bytes, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    write_attribute(&mut bytes, "screenWindowCenter", "v2f", &[0; 8]);
+    write_attribute(&mut bytes, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    bytes.push(0);
+
+    // One scanline per chunk, found through the table of their offsets.
+    let line_size = 4 * width as usize * channels.len();
+    let table_end = bytes.len() + 8 * height as usize;
+    for y in 0..height as usize {
+        let offset = table_end + y * (8 + line_size);
+        bytes.extend_from_slice(&(offset as u64).to_le_bytes());
+    }
+    for y in 0..height as usize {
+        bytes.extend_from_slice(&(y as i32).to_le_bytes());
+        bytes.extend_from_slice(&(line_size as i32).to_le_bytes());
+        for channel in &channels {
+            let row = &channel.values[y * width as usize..(y + 1) * width as usize];
+            for value in row {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+    bytes
+}
+
+pub fn write(path: &str, width: u32, height: u32, channels: &[Channel]) -> io::Result<()> {
+    fs::write(path, encode(width, height, channels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    fn read_i32(bytes: &[u8], offset: usize) -> i32 {
+        i32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    }
+
+    #[test]
+    fn test_encode() {
+        let (red, depth) = ([1.0, 2.0, 3.0, 4.0, 5.0, 6.0], [0.5; 6]);
+        let bytes = encode(3, 2, &[
+            Channel { name: String::from("R"), values: &red },
+            Channel { name: String::from("depth.Z"), values: &depth },
+        ]);
+        assert_eq!(read_i32(&bytes, 0), 20_000_630);
+        // The channels are listed in order.
+        let header = String::from_utf8_lossy(&bytes);
+        assert!(header.find("R\0").unwrap() < header.find("depth.Z\0").unwrap());
+
+        let line_size = 4 * 3 * 2;
+        let chunks = bytes.len() - 2 * (8 + line_size);
+        let table = chunks - 16;
+        for y in 0..2 {
+            let offset = u64::from_le_bytes(bytes[table + 8 * y..table + 8 * y + 8].try_into().unwrap()) as usize;
+            assert_eq!(offset, chunks + y * (8 + line_size));
+            assert_eq!(read_i32(&bytes, offset), y as i32);
+            assert_eq!(read_i32(&bytes, offset + 4), line_size as i32);
+            let first = f32::from_bits(read_i32(&bytes, offset + 8) as u32);
+            assert_eq!(first, red[3 * y]);
+        }
+    }
+}
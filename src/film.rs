@@ -0,0 +1,269 @@
+use std::f32::consts::PI;
+
+use crate::checkpoint::{self, Decoder};
+use crate::color::{Color, ColorU8};
+use crate::settings::FilterKind;
+use crate::tonemap::PostProcess;
+
+/// Reconstruction filter, separable in `x` and `y`, weighting a sample by
+/// its offset from a pixel centre in pixels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    /// Offset beyond which samples do not count, in pixels.
+    pub radius: f32,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, radius: f32) -> Filter {
+        Filter { kind, radius }
+    }
+
+    /// The usual radius of each filter.
+    pub fn default_radius(kind: FilterKind) -> f32 {
+        match kind {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+
+    pub fn evaluate(&self, dx: f32, dy: f32) -> f32 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        let radius = self.radius;
+        if x > radius {
+            return 0.0;
+        }
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => radius - x,
+            FilterKind::Gaussian => {
+                // The Gaussian is shifted to reach zero at the radius.
+                let sigma = radius / 3.0;
+                let gaussian = |x: f32| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            FilterKind::Mitchell => {
+                // Mitchell–Netravali with B = C = 1/3, stretched from
+                // [-2, 2] to the radius.
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                let x = 2.0 * x / radius;
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b)) / 6.0
+                } else {
+                    ((-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c)) / 6.0
+                }
+            }
+            FilterKind::Lanczos => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Pixels from `(x0, y0)` to `(x1, y1)` excluded.
+pub type PixelBounds = (u32, u32, u32, u32);
+
+/// Accumulates filtered samples over a rectangle of pixels, either the
+/// whole image or a tile and the margin its samples reach. Pixel `(i, j)`
+/// covers `[i, i + 1) x [j, j + 1)` in image coordinates, with `j` counted
+/// from the bottom.
+#[derive(Clone, Debug)]
+pub struct Film {
+    pub x0: u32,
+    pub y0: u32,
+    pub width: u32,
+    pub height: u32,
+    pub filter: Filter,
+    weighted_sums: Vec<Color>,
+    weight_sums: Vec<f32>,
+}
+
+impl Film {
+    pub fn new(x0: u32, y0: u32, width: u32, height: u32, filter: Filter) -> Film {
+        let size = (width * height) as usize;
+        Film {
+            x0,
+            y0,
+            width,
+            height,
+            filter,
+            weighted_sums: vec![Color::black(); size],
+            weight_sums: vec![0.0; size],
+        }
+    }
+
+    /// Film covering the pixels from `(x0, y0)` to `(x1, y1)` excluded and
+    /// the margin reached by the filter, within an image of the given size.
+    pub fn new_tile(x0: u32, y0: u32, x1: u32, y1: u32, image_size: (u32, u32), filter: Filter) -> Film {
+        let margin = (filter.radius - 0.5).ceil().max(0.0) as u32;
+        let (left, bottom) = (x0.saturating_sub(margin), y0.saturating_sub(margin));
+        let (right, top) = ((x1 + margin).min(image_size.0), (y1 + margin).min(image_size.1));
+        Film::new(left, bottom, right - left, top - bottom, filter)
+    }
+
+    /// Adds a sample taken at `(x, y)` in image coordinates to the pixels
+    /// whose centre is within the filter radius, as far as the pixels of
+    /// `view`, the part of the image showing what the sample does.
+    pub fn add_sample(&mut self, x: f32, y: f32, view: PixelBounds, color: Color) {
+        let radius = self.filter.radius;
+        let first_x = ((x - 0.5 - radius).ceil().max(self.x0.max(view.0) as f32)) as u32;
+        let first_y = ((y - 0.5 - radius).ceil().max(self.y0.max(view.1) as f32)) as u32;
+        let last_x = ((x - 0.5 + radius).floor() as i64).min(i64::from((self.x0 + self.width).min(view.2)) - 1);
+        let last_y = ((y - 0.5 + radius).floor() as i64).min(i64::from((self.y0 + self.height).min(view.3)) - 1);
+        for j in i64::from(first_y)..=last_y {
+            for i in i64::from(first_x)..=last_x {
+                let weight = self.filter.evaluate(i as f32 + 0.5 - x, j as f32 + 0.5 - y);
+                if weight == 0.0 {
+                    continue;
+                }
+                let index = self.index(i as u32, j as u32);
+                self.weighted_sums[index] += weight * color;
+                self.weight_sums[index] += weight;
+            }
+        }
+    }
+
+    fn index(&self, i: u32, j: u32) -> usize {
+        ((j - self.y0) * self.width + (i - self.x0)) as usize
+    }
+
+    /// Adds the sums of `tile`, which must lie within this film.
+    pub fn merge(&mut self, tile: &Film) {
+        for j in 0..tile.height {
+            for i in 0..tile.width {
+                let from = (j * tile.width + i) as usize;
+                let to = self.index(tile.x0 + i, tile.y0 + j);
+                self.weighted_sums[to] += tile.weighted_sums[from];
+                self.weight_sums[to] += tile.weight_sums[from];
+            }
+        }
+    }
+
+    /// Filtered colour of pixel `(i, j)`.
+    pub fn pixel(&self, i: u32, j: u32) -> Color {
+        let index = self.index(i, j);
+        let weight = self.weight_sums[index];
+        if weight == 0.0 {
+            return Color::black();
+        }
+        self.weighted_sums[index] / weight
+    }
+
+    /// Replaces the samples of pixel `(i, j)` by the colour `color`.
+    pub fn set_pixel(&mut self, i: u32, j: u32, color: Color) {
+        let index = self.index(i, j);
+        self.weighted_sums[index] = color;
+        self.weight_sums[index] = 1.0;
+    }
+
+    /// Appends the sums of the film to a checkpoint.
+    pub fn encode(&self, bytes: &mut Vec<u8>) {
+        for (sum, weight) in self.weighted_sums.iter().zip(self.weight_sums.iter()) {
+            for value in &[sum.red, sum.green, sum.blue, *weight] {
+                checkpoint::put_f32(bytes, *value);
+            }
+        }
+    }
+
+    /// Reads back the sums written by `encode` for a film of this size.
+    pub fn decode(&mut self, decoder: &mut Decoder) -> Result<(), String> {
+        for (sum, weight) in self.weighted_sums.iter_mut().zip(self.weight_sums.iter_mut()) {
+            *sum = Color::new(decoder.f32()?, decoder.f32()?, decoder.f32()?);
+            *weight = decoder.f32()?;
+        }
+        Ok(())
+    }
+
+    /// Pixels of the film row by row from the top, encoded by
+    /// `post_process`, which also clamps the undershoot of the negative
+    /// lobes of the filters.
+    pub fn to_pixels(&self, post_process: &PostProcess) -> Vec<ColorU8> {
+        let mut pixels = Vec::with_capacity((self.width * self.height) as usize);
+        for j in (self.y0..self.y0 + self.height).rev() {
+            for i in self.x0..self.x0 + self.width {
+                pixels.push(post_process.encode(self.pixel(i, j), i, j));
+            }
+        }
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters() {
+        for kind in &[FilterKind::Box, FilterKind::Tent, FilterKind::Gaussian, FilterKind::Mitchell, FilterKind::Lanczos] {
+            let filter = Filter::new(*kind, Filter::default_radius(*kind));
+            assert!(filter.evaluate(0.0, 0.0) > 0.0);
+            assert_eq!(filter.evaluate(filter.radius + 0.1, 0.0), 0.0);
+            assert!((filter.evaluate(0.3, -0.2) - filter.evaluate(-0.3, 0.2)).abs() < 1e-6);
+        }
+        let tent = Filter::new(FilterKind::Tent, 1.0);
+        assert!(tent.evaluate(0.5, 0.0) < tent.evaluate(0.0, 0.0));
+        // Mitchell–Netravali and Lanczos have negative lobes.
+        assert!(Filter::new(FilterKind::Mitchell, 2.0).evaluate(1.5, 0.0) < 0.0);
+        assert!(Filter::new(FilterKind::Lanczos, 3.0).evaluate(1.5, 0.0) < 0.0);
+        assert!((Filter::new(FilterKind::Lanczos, 3.0).evaluate(1.0, 0.0)).abs() < 1e-6);
+    }
+    #[test]
+    fn test_box_film_keeps_samples_in_their_pixel() {
+        let mut film = Film::new(0, 0, 3, 3, Filter::new(FilterKind::Box, 0.5));
+        film.add_sample(1.2, 1.7, (0, 0, 3, 3), Color::white());
+        film.add_sample(1.9, 1.1, (0, 0, 3, 3), Color::black());
+        assert_eq!(film.pixel(1, 1).red, 0.5);
+        assert_eq!(film.weight_sums.iter().filter(|weight| **weight > 0.0).count(), 1);
+    }
+    #[test]
+    fn test_film_keeps_samples_in_their_view() {
+        let mut film = Film::new(0, 0, 4, 1, Filter::new(FilterKind::Gaussian, 1.5));
+        film.add_sample(1.9, 0.5, (0, 0, 2, 1), Color::white());
+        film.add_sample(2.1, 0.5, (2, 0, 4, 1), Color::black());
+        assert_eq!(film.pixel(1, 0).red, 1.0);
+        assert_eq!(film.pixel(2, 0).red, 0.0);
+        assert!(film.pixel(0, 0).red > 0.0 && film.weight_sums[3] > 0.0);
+    }
+    #[test]
+    fn test_film_splats_and_merges() {
+        let filter = Filter::new(FilterKind::Tent, 1.0);
+        let mut film = Film::new(0, 0, 4, 4, filter);
+        film.add_sample(1.7, 1.5, (0, 0, 4, 4), Color::white());
+        film.add_sample(2.2, 1.5, (0, 0, 4, 4), Color::black());
+        // Each sample reaches the pixel of the other one.
+        assert!(film.pixel(1, 1).red < 1.0);
+        assert!(film.pixel(2, 1).red > 0.0);
+
+        let mut merged = Film::new(0, 0, 4, 4, filter);
+        let mut left = Film::new_tile(0, 0, 2, 4, (4, 4), filter);
+        let mut right = Film::new_tile(2, 0, 4, 4, (4, 4), filter);
+        assert_eq!((left.width, right.x0), (3, 1));
+        left.add_sample(1.7, 1.5, (0, 0, 4, 4), Color::white());
+        right.add_sample(2.2, 1.5, (0, 0, 4, 4), Color::black());
+        merged.merge(&left);
+        merged.merge(&right);
+        for j in 0..4 {
+            for i in 0..4 {
+                assert_eq!(merged.pixel(i, j).red, film.pixel(i, j).red);
+            }
+        }
+    }
+}
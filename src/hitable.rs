@@ -1,23 +1,65 @@
+use std::collections::HashMap;
 use std::fmt;
+use std::f32::consts::PI;
 use crate::vec3::Vec3;
 use crate::ray::Ray;
-use crate::material::{Material, HitRecord};
+use crate::material::{Material, MaterialRef, HitRecord};
+use crate::sampler::Sampler;
 
+/// Something rays can hit.
 pub trait Hitable {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+    /// Nearest hit along `ray` between `t_min` and `t_max`, whatever the
+    /// opacity of the material there, see `hit_opaque`.
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>>;
+
+    /// Like `hit`, along with the index of the object hit among those
+    /// making up `self`.
+    fn hit_object(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(usize, HitRecord<'_>)> {
+        self.hit(ray, t_min, t_max).map(|hit_record| (0, hit_record))
+    }
+
+    /// Material of the surface, for shapes made of a single one.
+    fn material(&self) -> Option<&dyn Material> {
+        None
+    }
+
+    /// Intersection tests run by a call of `hit`, one for each shape.
+    fn shape_count(&self) -> usize {
+        1
+    }
 }
 
-pub struct Sphere<M: Material + Clone> {
+/// Nearest hit of `world` along `ray` that stops it, along with the index
+/// of the object hit. Partially transparent surfaces stop the ray with the
+/// probability of their opacity, drawn from `sampler`, and are crossed
+/// otherwise as if there were no geometry there. The intersection tests
+/// run, crossed surfaces included, are added to `tests`.
+pub fn hit_opaque<'a, T: Hitable + ?Sized>(
+    world: &'a T, ray: &Ray, t_min: f32, t_max: f32, sampler: &mut dyn Sampler, tests: &mut u64,
+) -> Option<(usize, HitRecord<'a>)> {
+    let mut t_min = t_min;
+    loop {
+        *tests += world.shape_count() as u64;
+        let (object, hit_record) = world.hit_object(ray, t_min, t_max)?;
+        let opacity = hit_record.material.opacity(&hit_record);
+        if opacity >= 1.0 || (opacity > 0.0 && sampler.get_1d() < opacity) {
+            return Some((object, hit_record));
+        }
+        t_min = hit_record.t_factor;
+    }
+}
+
+pub struct Sphere {
     center: Vec3,
     radius: f32,
-    material: M,
+    material: MaterialRef,
 }
-impl<M: Material + Clone> Sphere<M> {
-    pub fn new(center: Vec3, radius: f32, material: M) -> Sphere<M> {
+impl Sphere {
+    pub fn new(center: Vec3, radius: f32, material: MaterialRef) -> Sphere {
         Sphere { center, radius, material }
     }
 }
-impl<M: Material + Clone> fmt::Display for Sphere<M> {
+impl fmt::Display for Sphere {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
@@ -28,8 +70,21 @@ impl<M: Material + Clone> fmt::Display for Sphere<M> {
     }
 }
 
-impl<M: Material + Clone> Hitable for Sphere<M> {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+/// Texture coordinates of the point of a unit sphere whose position
+/// relative to the center is `normal`; `v` runs from the bottom (-z) to the
+/// top (+z) pole.
+fn sphere_uv(normal: &Vec3) -> (f32, f32) {
+    let phi = normal.y.atan2(normal.x);
+    let theta = normal.z.clamp(-1.0, 1.0).asin();
+    ((phi + PI) / (2.0 * PI), theta / PI + 0.5)
+}
+
+impl Hitable for Sphere {
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let oc = ray.origin - self.center;
         let a = ray.direction.dot(ray.direction);
         let b = oc.dot(ray.direction);
@@ -38,41 +93,49 @@ impl<M: Material + Clone> Hitable for Sphere<M> {
         if discriminant <= 0.0 {
             return None;
         }
-        let mut t = (-b - discriminant.sqrt()) / a;
-        if t < t_max && t > t_min {
-            let vector_p = ray.point_at_t(t);
-            return Some(HitRecord{
-                t_factor: t,
-                p_vect: vector_p,
-                normal: (vector_p  - self.center) / self.radius,
-                material: &self.material,
-            })
-        }
-        t = (-b + discriminant.sqrt()) / a;
-        if t < t_max && t > t_min {
+        let root = discriminant.sqrt();
+        for t in [(-b - root) / a, (-b + root) / a].iter().cloned() {
+            if !(t < t_max && t > t_min) {
+                continue;
+            }
             let vector_p = ray.point_at_t(t);
-            return Some(HitRecord{
+            let normal = (vector_p  - self.center) / self.radius;
+            let (u, v) = sphere_uv(&normal);
+            let around_pole = Vec3::new(-normal.y, normal.x, 0.0);
+            let tangent = if around_pole.square_length() > 1e-12 {
+                around_pole.make_unit_vector()
+            } else {
+                normal.orthonormal_basis().0
+            };
+            let hit_record = HitRecord{
                 t_factor: t,
                 p_vect: vector_p,
-                normal: (vector_p  - self.center) / self.radius,
-                material: &self.material,
-            })
+                normal,
+                tangent,
+                u,
+                v,
+                front_face: ray.direction.dot(normal) < 0.0,
+                material: self.material.as_ref(),
+            };
+            return Some(hit_record);
         }
         None
     }
 }
-pub struct Plane <M: Material + Clone>{
+/// Plane through `origin`, stopping the rays that cross it along `normal`:
+/// it is seen from the side opposite to `normal`, which its hits face.
+pub struct Plane {
     pub origin: Vec3,
     pub normal: Vec3,
-    pub material: M,
+    pub material: MaterialRef,
 }
 
-impl<M: Material + Clone> Plane<M> {
-    pub fn new(origin: Vec3, normal: Vec3, material: M) -> Plane<M> {
+impl Plane {
+    pub fn new(origin: Vec3, normal: Vec3, material: MaterialRef) -> Plane {
         Plane { origin, normal, material }
     }
 }
-impl<M: Material + Clone> fmt::Display for Plane<M> {
+impl fmt::Display for Plane {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
@@ -82,40 +145,210 @@ impl<M: Material + Clone> fmt::Display for Plane<M> {
         )
     }
 }
-impl<M: Material + Clone> Hitable for Plane<M> {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+impl Hitable for Plane {
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let denom = self.normal.dot(ray.direction);
         if denom > 1e-6 {
             let v = self.origin - ray.origin;
             let distance = v.dot(self.normal) / denom;
-            if distance >= 0.0 {
-                return Some(HitRecord{
+            if distance < t_max && distance > t_min {
+                let vector_p = ray.point_at_t(distance);
+                let normal = -self.normal.make_unit_vector();
+                let (tangent, bitangent) = normal.orthonormal_basis();
+                let hit_record = HitRecord{
                     t_factor: distance,
-                    p_vect: v,
-                    normal: self.normal,
-                    material: &self.material,
-                });
+                    p_vect: vector_p,
+                    normal,
+                    tangent,
+                    u: (vector_p - self.origin).dot(tangent),
+                    v: (vector_p - self.origin).dot(bitangent),
+                    front_face: true,
+                    material: self.material.as_ref(),
+                };
+                return Some(hit_record);
             }
         }
         None
     }
 }
 
+/// Parallelogram spanned by the edges `edge_u` and `edge_v` from the corner
+/// `origin`, with texture coordinates running from `0` to `1` along each
+/// edge. The normal is `edge_u × edge_v`.
+pub struct Quad {
+    pub origin: Vec3,
+    pub edge_u: Vec3,
+    pub edge_v: Vec3,
+    pub material: MaterialRef,
+}
+
+impl Quad {
+    pub fn new(origin: Vec3, edge_u: Vec3, edge_v: Vec3, material: MaterialRef) -> Quad {
+        Quad { origin, edge_u, edge_v, material }
+    }
+}
+
+impl Hitable for Quad {
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let cross = self.edge_u.cross(self.edge_v);
+        let denom = cross.dot(ray.direction);
+        if denom.abs() < 1e-9 {
+            return None;
+        }
+        let t = cross.dot(self.origin - ray.origin) / denom;
+        if !(t < t_max && t > t_min) {
+            return None;
+        }
+        let vector_p = ray.point_at_t(t);
+        // Coordinates of the hit point in the basis of the edges.
+        let offset = vector_p - self.origin;
+        let inverse_area2 = 1.0 / cross.square_length();
+        let u = offset.cross(self.edge_v).dot(cross) * inverse_area2;
+        let v = self.edge_u.cross(offset).dot(cross) * inverse_area2;
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+        let normal = cross.make_unit_vector();
+        let hit_record = HitRecord{
+            t_factor: t,
+            p_vect: vector_p,
+            normal,
+            tangent: self.edge_u.make_unit_vector(),
+            u,
+            v,
+            front_face: denom < 0.0,
+            material: self.material.as_ref(),
+        };
+        Some(hit_record)
+    }
+}
+
+/// Objects rendered together, rays hitting the nearest of them.
+#[derive(Default)]
 pub struct Scene {
     pub hitlist: Vec<Box<dyn Hitable + Send + Sync + 'static>>,
+    /// Index in `hitlist` of each named object.
+    pub names: HashMap<String, usize>,
 }
 
+impl Scene {
+    pub fn new() -> Scene {
+        Scene::default()
+    }
+
+    /// Object named `name`.
+    pub fn object(&self, name: &str) -> Option<&(dyn Hitable + Send + Sync)> {
+        self.names.get(name).map(|index| self.hitlist[*index].as_ref())
+    }
 
-impl<'a> Hitable for Scene {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let mut hit_anything: Option<HitRecord> = None;
+    /// Number of the material of each object, from 1 in the order the
+    /// materials first appear in `hitlist`, the same for objects sharing a
+    /// material; 0 for objects without a single material.
+    pub fn material_numbers(&self) -> Vec<u32> {
+        let mut materials: Vec<*const ()> = Vec::new();
+        self.hitlist.iter().map(|hitable| match hitable.material() {
+            Some(material) => {
+                let address = material.identity();
+                match materials.iter().position(|known| *known == address) {
+                    Some(index) => index as u32 + 1,
+                    None => {
+                        materials.push(address);
+                        materials.len() as u32
+                    }
+                }
+            }
+            None => 0,
+        }).collect()
+    }
+}
+
+
+impl Hitable for Scene {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.hit_object(ray, t_min, t_max).map(|(_, hit_record)| hit_record)
+    }
+
+    fn hit_object(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(usize, HitRecord<'_>)> {
+        let mut hit_anything: Option<(usize, HitRecord)> = None;
         let mut closest_so_far = t_max;
-        for hitable in self.hitlist.iter() {
+        for (index, hitable) in self.hitlist.iter().enumerate() {
             if let Some(hit_temp) = hitable.hit(ray, t_min, closest_so_far) {
                 closest_so_far = hit_temp.t_factor;
-                hit_anything = Some(hit_temp);
+                hit_anything = Some((index, hit_temp));
             }
         }
         hit_anything
     }
+
+    fn shape_count(&self) -> usize {
+        self.hitlist.iter().map(|hitable| hitable.shape_count()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::color::Color;
+    use crate::material::{AlphaMasked, Lambertian};
+    use crate::sampler::IndependentSampler;
+    use crate::texture::ConstantTexture;
+
+    #[test]
+    fn test_quad_hit() {
+        let quad = Quad::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Arc::new(Lambertian::new(Color::white())),
+        );
+        let ray = Ray::new(Vec3::new(0.5, -1.0, 0.25), Vec3::new(0.0, 1.0, 0.0));
+        let hit = quad.hit(&ray, 0.001, f32::MAX).unwrap();
+        assert!((hit.t_factor - 1.0).abs() < 1e-5);
+        assert!((hit.u - 0.25).abs() < 1e-5);
+        assert!((hit.v - 0.25).abs() < 1e-5);
+        assert!(hit.front_face);
+        let outside = Ray::new(Vec3::new(2.5, -1.0, 0.25), Vec3::new(0.0, 1.0, 0.0));
+        assert!(quad.hit(&outside, 0.001, f32::MAX).is_none());
+    }
+    #[test]
+    fn test_alpha_masked_sphere() {
+        let ray = Ray::new(Vec3::new(0.0, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let clear = Sphere::new(Vec3::new_zero(), 1.0, Arc::new(AlphaMasked::new(
+            Lambertian::new(Color::white()),
+            ConstantTexture::new_f32_ref(0.0),
+        )));
+        let mut sampler = IndependentSampler::new(0);
+        let mut tests = 0;
+        assert!(hit_opaque(&clear, &ray, 0.001, f32::MAX, &mut sampler, &mut tests).is_none());
+        // Both sides are crossed, then the ray leaves the sphere.
+        assert_eq!(tests, 3);
+        let half = Sphere::new(Vec3::new_zero(), 1.0, Arc::new(AlphaMasked::new(
+            Lambertian::new(Color::white()),
+            ConstantTexture::new_f32_ref(0.5),
+        )));
+        let n = 10000;
+        let mut near = 0;
+        let mut far = 0;
+        for i in 0..n {
+            sampler.start_pixel_sample(0, 0, i);
+            match hit_opaque(&half, &ray, 0.001, f32::MAX, &mut sampler, &mut tests) {
+                Some((_, hit)) if hit.t_factor < 5.0 => near += 1,
+                Some(_) => far += 1,
+                None => {}
+            }
+        }
+        // Each side stops the ray with probability one half.
+        assert!((near as f32 / n as f32 - 0.5).abs() < 0.03);
+        assert!((far as f32 / n as f32 - 0.25).abs() < 0.03);
+    }
 }
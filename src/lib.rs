@@ -1,78 +1,369 @@
+//! A path tracer. A `Renderer` renders a `Scene` of objects from the
+//! `hitable` module, made of materials from the `material` module, through
+//! a `Camera` of the `camera` module into an `Image` in memory. A `SceneBuilder` builds both with
+//! materials shared between objects.
+//!
+//! ```
+//! use std::sync::Arc;
+//!
+//! use raytracer::{RenderSettings, Renderer, Scene};
+//! use raytracer::camera::PerspectiveCamera;
+//! use raytracer::color::Color;
+//! use raytracer::hitable::Sphere;
+//! use raytracer::material::Lambertian;
+//! use raytracer::vec3::Vec3;
+//!
+//! let mut scene = Scene::new();
+//! let red = Lambertian::new(Color::new(0.8, 0.2, 0.2));
+//! scene.hitlist.push(Box::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Arc::new(red))));
+//! let settings = RenderSettings { x_size: 32, y_size: 24, s_size: 4, ..RenderSettings::default() };
+//! let camera = PerspectiveCamera::new(
+//!     Vec3::new(0.0, -6.0, 0.0), Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0),
+//!     30.0, 32.0 / 24.0, 0.0, 6.0,
+//! );
+//! let image = Renderer::new(scene, camera, settings).unwrap().render();
+//! assert_eq!(image.pixels.len(), 32 * 24);
+//! ```
+
 use rand::prelude::*;
+use rand::rngs::SmallRng;
 
-mod vec3;
-mod color;
-mod hitable;
-mod ray;
-mod camera;
-mod material;
+pub mod vec3;
+pub mod color;
+pub mod hitable;
+pub mod ray;
+pub mod camera;
+pub mod material;
+mod spectrum;
+mod settings;
+pub mod texture;
+pub mod medium;
+pub mod sampler;
+mod adaptive;
+mod film;
+mod exr;
+mod aov;
+mod denoise;
+mod tonemap;
+mod checkpoint;
+mod control;
+mod stats;
+mod builder;
 
-use camera::Camera;
+pub use camera::Camera;
+use camera::{
+    Aperture, EquirectangularCamera, FisheyeCamera, Lens, OrthographicCamera, PerspectiveCamera, StereoCamera,
+    StereoLayout,
+};
 use vec3::Vec3;
 use color::Color;
 use color::ColorU8;
 use ray::Ray;
+use material::Scatter;
+use material::Material;
 use hitable::Hitable;
+use hitable::hit_opaque;
 use hitable::Sphere;
 use hitable::Plane;
-use hitable::Scene;
+use hitable::Quad;
+pub use hitable::Scene;
 use material::Metal;
 use material::Lambertian;
 use material::Dielectric;
+use material::Ior;
+use material::Principled;
+use material::NormalMapped;
+use material::BumpMapped;
+use material::Mix;
+use material::TwoSided;
+use material::Coated;
+use material::OrenNayar;
+use material::DiffuseTransmission;
+use material::Subsurface;
+use material::ThinFilm;
+use material::AlphaMasked;
+use medium::Medium;
+use texture::ConstantTexture;
+use texture::CheckerTexture;
+use texture::ImageTexture;
+use spectrum::Radiance;
+use spectrum::SampledSpectrum;
+use spectrum::SampledWavelengths;
+use sampler::Sampler;
+use adaptive::PixelStatistics;
+use film::{ Film, Filter };
+use aov::{ AovFilm, FirstHit, PathRecord };
+use tonemap::PostProcess;
+pub use settings::RenderSettings;
+pub use settings::SceneKind;
+pub use settings::CameraKind;
+pub use settings::StereoKind;
+pub use settings::SamplerKind;
+pub use settings::FilterKind;
+pub use settings::ToneMapKind;
+pub use settings::AovKind;
+pub use aov::Layer;
+pub use control::RenderController;
+pub use builder::SceneBuilder;
+pub use stats::{ Progress, RayCounts, RenderStatistics };
 
 use std::thread;
-use std::sync::{ Arc, Mutex };
-use std::collections::HashMap;
-
-fn render_color(
-    resolution: u16,
-    i: f32,
-    j: f32,
-    x_size: f32,
-    y_size: f32,
-    camera: &Camera,
-    random_scene: &Scene
-) -> ColorU8 {
-    let mut rng = thread_rng();
-    let mut rendered_color = Color::black();
-    for _res in 0..resolution {
-        let u = (i + rng.gen_range(0.0, 1.0)) / x_size;
-        let v = (j + rng.gen_range(0.0, 1.0)) / y_size;
-        let r = camera.get_ray(u, v);
-        rendered_color += color(&r, random_scene, 0);
-    }
-    rendered_color /= resolution as f32;
-    rendered_color = rendered_color.sqrt();
-    rendered_color *= f32::from(std::u8::MAX);
-    ColorU8::make_from_color(rendered_color)
+use std::sync::{ mpsc, Arc };
+use std::sync::atomic::{ AtomicU64, AtomicUsize, Ordering };
+use std::ops::Range;
+use std::fs;
+use std::time::{ Duration, Instant };
+use std::io;
+
+/// Side of the square tiles the image is rendered by, in pixels.
+const TILE_SIZE: u32 = 16;
+
+/// Pixels from `(x0, y0)` to `(x1, y1)` excluded.
+type TileBounds = (u32, u32, u32, u32);
+
+/// What the samples of a tile in a pass are accumulated into.
+#[derive(Clone)]
+struct RenderedTile {
+    bounds: TileBounds,
+    /// Indices of the samples of each pixel taken in the pass.
+    samples: Range<u16>,
+    film: Film,
+    /// AOVs of the tile, if they are rendered.
+    aovs: Option<AovFilm>,
+    /// Statistics of the samples of each pixel since the first pass, row by
+    /// row from the bottom.
+    statistics: Vec<PixelStatistics>,
+    /// Samples and rays of the pass.
+    rays: RayCounts,
 }
 
-fn color<T: Hitable>(ray: &Ray, world: &T, depth: u8) -> Color {
-    if let Some(hit_record) = world.hit(ray, 0.001, std::f32::MAX) {
-        if depth < 50 {
-            if let Some(scatter) = hit_record.material.scatter(ray, &hit_record) { 
-                if let Some(scattered_ray) = scatter.ray {
-                    return scatter.color * color(&scattered_ray, world, depth + 1);
-                }
+impl RenderedTile {
+    /// Tile starting from the statistics of the previous passes, kept for
+    /// the whole image in `image_statistics`.
+    fn new(
+        bounds: TileBounds, samples: Range<u16>, settings: &RenderSettings, filter: Filter,
+        image_statistics: &[PixelStatistics],
+    ) -> RenderedTile {
+        let (x0, y0, x1, y1) = bounds;
+        let image_size = (settings.x_size, settings.y_size);
+        let mut statistics = Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize);
+        for j in y0..y1 {
+            for i in x0..x1 {
+                statistics.push(image_statistics[(j * settings.x_size + i) as usize]);
+            }
+        }
+        RenderedTile {
+            bounds,
+            samples,
+            film: Film::new_tile(x0, y0, x1, y1, image_size, filter),
+            aovs: if settings.aovs.is_empty() && !settings.denoise {
+                None
             } else {
-                return Color::black();
+                Some(AovFilm::new_tile(x0, y0, x1, y1, image_size, filter))
+            },
+            statistics,
+            rays: RayCounts::default(),
+        }
+    }
+}
+
+/// Adds the samples of the pass of `tile` to the pixel `(i, j)`, returning
+/// the statistics of all its samples so far. With adaptive sampling, the
+/// error is checked every `min_samples` samples, through `post`, so that the
+/// samples taken stay whole batches of the sampler.
+fn render_pixel(
+    settings: &RenderSettings,
+    post: &PostProcess,
+    (i, j): (u32, u32),
+    camera: &dyn Camera,
+    random_scene: &Scene,
+    sampler: &mut dyn Sampler,
+    tile: &mut RenderedTile,
+) -> PixelStatistics {
+    let (x0, y0, x1, _) = tile.bounds;
+    let pixel = ((j - y0) * (x1 - x0) + (i - x0)) as usize;
+    let mut statistics = tile.statistics[pixel];
+    let min_samples = settings.min_samples.min(settings.s_size);
+    for index in tile.samples.clone() {
+        if u32::from(index) != statistics.count {
+            // The pixel stopped in an earlier pass.
+            break;
+        }
+        if let Some(threshold) = settings.adaptive_threshold {
+            if index > 0 && index % min_samples == 0 && statistics.error(post) < threshold {
+                break;
             }
+        }
+        sampler.start_pixel_sample(i, j, u32::from(index));
+        let (jitter_u, jitter_v) = sampler.get_2d();
+        let (x, y) = (i as f32 + jitter_u, j as f32 + jitter_v);
+        let u = x / settings.x_size as f32;
+        let v = y / settings.y_size as f32;
+        let (r, weight) = camera.get_weighted_ray(u, v, sampler);
+        // Pixels straddling the edge of a view take samples of both sides.
+        let (u0, v0, u1, v1) = camera.view(u, v);
+        let view = (
+            (u0 * settings.x_size as f32).floor() as u32, (v0 * settings.y_size as f32).floor() as u32,
+            (u1 * settings.x_size as f32).ceil() as u32, (v1 * settings.y_size as f32).ceil() as u32,
+        );
+        let aovs = tile.aovs.is_some();
+        let (sample_color, path) = if weight == 0.0 {
+            // Blocked before the image: a black sample, with nothing to trace.
+            (Color::black(), if aovs { Some(PathRecord::new(&())) } else { None })
+        } else if settings.spectral {
+            let mut wavelengths = SampledWavelengths::sample_uniform(sampler.get_1d());
+            let mut path = if aovs { Some(PathRecord::new(&wavelengths)) } else { None };
+            let radiance: SampledSpectrum =
+                color(&r, random_scene, &mut wavelengths, sampler, &mut path, &mut tile.rays);
+            (radiance.to_color(&wavelengths), path.map(|path| path.to_color(&wavelengths)))
         } else {
-            return Color::black();
+            let mut path = if aovs { Some(PathRecord::new(&())) } else { None };
+            (color::<Color, _>(&r, random_scene, &mut (), sampler, &mut path, &mut tile.rays), path)
+        };
+        let sample_color = weight * sample_color;
+        tile.film.add_sample(x, y, view, sample_color);
+        if let (Some(aovs), Some(path)) = (tile.aovs.as_mut(), path) {
+            aovs.add_sample(x, y, view, &path.weighted(weight));
         }
+        statistics.add(sample_color);
+        tile.rays.samples += 1;
     }
+    tile.statistics[pixel] = statistics;
+    statistics
+}
+
+fn sky(ray: &Ray) -> Color {
     let unit_direction = ray.direction.make_unit_vector();
     let t = 0.5 * (unit_direction.y + 1.0);
     (1.0 - t) * Color::white() + t * Color{ red: 0.5, green: 0.7, blue: 1.0 }
 }
 
-pub fn random_scene() -> Scene {
-    let mut rng = thread_rng();
-    let mut world = Scene{ hitlist: Vec::new() };
+/// Longest random walk through a medium before the path is abandoned.
+const MAX_WALK_STEPS: u32 = 1024;
+
+/// Bounces before paths may be terminated by Russian roulette.
+const ROULETTE_DEPTH: u32 = 5;
+
+/// Radiance arriving along `ray`, carried as RGB or as a spectrum at
+/// `wavelengths` depending on `R`. How it splits, and what the ray hit
+/// first, is recorded in `path` when AOVs are rendered, and the rays
+/// traced in `rays`.
+fn color<R: Radiance, T: Hitable>(
+    ray: &Ray, world: &T, wavelengths: &mut R::Wavelengths, sampler: &mut dyn Sampler,
+    path: &mut Option<PathRecord<R>>, rays: &mut RayCounts,
+) -> R {
+    let mut ray = Ray { wavelength: R::wavelength(wavelengths), ..*ray };
+    let mut throughput = R::from_color(Color::white(), wavelengths);
+    let mut radiance = R::from_color(Color::black(), wavelengths);
+    let mut medium: Option<Medium> = None;
+    for depth in 0..=50 {
+        let mut hit = if depth == 0 {
+            rays.primary += 1;
+            let hit = hit_opaque(world, &ray, 0.001, f32::MAX, sampler, &mut rays.intersection_tests);
+            if let (Some(path), Some((object, hit_record))) = (path.as_mut(), hit) {
+                path.first_hit = Some(FirstHit {
+                    distance: hit_record.t_factor * ray.direction.length(),
+                    position: hit_record.p_vect,
+                    normal: hit_record.normal,
+                    object,
+                });
+            }
+            hit.map(|(_, hit_record)| hit_record)
+        } else {
+            rays.secondary += 1;
+            hit_opaque(world, &ray, 0.001, f32::MAX, sampler, &mut rays.intersection_tests)
+                .map(|(_, hit_record)| hit_record)
+        };
+        if let Some(medium) = medium {
+            // Random walk inside the medium until it reaches its boundary.
+            let mut steps = 0;
+            loop {
+                let distance = medium.sample_distance(sampler);
+                let boundary = hit.map_or(f32::MAX, |hit_record| hit_record.t_factor * ray.direction.length());
+                if distance >= boundary {
+                    break;
+                }
+                steps += 1;
+                if steps > MAX_WALK_STEPS {
+                    return radiance;
+                }
+                throughput *= R::from_color(medium.albedo, wavelengths);
+                let direction = ray.direction.make_unit_vector();
+                ray = Ray {
+                    origin: ray.origin + distance * direction,
+                    direction: medium.sample_direction(&direction, sampler),
+                    ..ray
+                };
+                rays.secondary += 1;
+                hit = hit_opaque(world, &ray, 0.0, f32::MAX, sampler, &mut rays.intersection_tests)
+                    .map(|(_, hit_record)| hit_record);
+            }
+        }
+        let hit_record = match hit {
+            Some(hit_record) => hit_record,
+            None => {
+                let light = throughput * R::from_color(sky(&ray), wavelengths);
+                if let Some(path) = path {
+                    if depth == 0 {
+                        path.albedo = sky(&ray);
+                    }
+                    path.add(light, depth);
+                }
+                return radiance + light;
+            }
+        };
+        let light = throughput * R::from_color(hit_record.material.emitted(&hit_record), wavelengths);
+        if let Some(path) = path {
+            path.add(light, depth);
+        }
+        radiance += light;
+        if depth == 50 {
+            break;
+        }
+        if depth >= ROULETTE_DEPTH {
+            // Paths carrying little light are terminated, the survivors
+            // being weighted up to keep the estimate unbiased.
+            let survival = throughput.max_component().min(0.95);
+            if sampler.get_1d() >= survival {
+                break;
+            }
+            throughput *= 1.0 / survival;
+        }
+        match hit_record.material.scatter(&ray, &hit_record, sampler) {
+            Some(Scatter { color, ray: Some(scattered), lobe }) => {
+                if let Some(path) = path {
+                    if depth == 0 {
+                        path.albedo = color;
+                    }
+                    if let Some(lobe) = lobe {
+                        path.scattered(lobe);
+                    }
+                }
+                if hit_record.material.is_dispersive() {
+                    R::terminate_secondary(wavelengths);
+                }
+                if let Some(interior) = hit_record.material.interior() {
+                    let entering = scattered.direction.dot(hit_record.normal) < 0.0;
+                    medium = if entering { Some(interior) } else { None };
+                }
+                throughput *= R::from_color(color, wavelengths);
+                ray = Ray { wavelength: ray.wavelength, ..scattered };
+            }
+            _ => break,
+        }
+    }
+    radiance
+}
+
+/// The field of small spheres around three large ones, laid out from
+/// `seed`.
+pub fn random_scene(seed: u64) -> Scene {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut world = Scene::new();
     let obj0 = Sphere::new(
         Vec3::new(0.0, 0.0, -1000.0),
         1000.0,
-        Lambertian::new(Color{ red: 1.0, green: 0.6, blue: 0.5 })
+        Arc::new(Lambertian::new(Color{ red: 1.0, green: 0.6, blue: 0.5 }))
     );
     world.hitlist.push(Box::new(obj0));
     for a in -11..11 {
@@ -87,12 +378,12 @@ pub fn random_scene() -> Scene {
                 if choose_mat < 0.8 {
                     world.hitlist.push(
                         Box::new(
-                            Sphere::new(center, 0.2, Lambertian::new(
+                            Sphere::new(center, 0.2, Arc::new(Lambertian::new(
                                 Color {
                                     red: rng.gen_range(0.0, 1.0)*rng.gen_range(0.0, 1.0),
                                     green: rng.gen_range(0.0, 1.0)*rng.gen_range(0.0, 1.0),
                                     blue: rng.gen_range(0.0, 1.0)*rng.gen_range(0.0, 1.0)
-                                })
+                                }))
                             )
                         )
                     );
@@ -102,24 +393,31 @@ pub fn random_scene() -> Scene {
                             Sphere::new(
                                 center,
                                 0.2,
-                                Metal::new(
+                                Arc::new(Metal::new(
                                     Color {
                                         red: 0.5 * (1.0 + rng.gen_range(0.0, 1.0)),
                                         green: 0.5 * (1.0 + rng.gen_range(0.0, 1.0)),
                                         blue: 0.5 * (1.0 + rng.gen_range(0.0, 1.0))
                                     },
                                     0.5 * rng.gen_range(0.0, 1.0) 
-                                ),
+                                )),
                             )
                         )
                     );
                 } else {
-                   world.hitlist.push(
+                    let glass = if choose_mat < 0.97 {
+                        Dielectric::new(1.5)
+                    } else if choose_mat < 0.985 {
+                        Dielectric::new_rough(1.5, 0.3, Color::black())
+                    } else {
+                        Dielectric::new_rough(1.33, 0.0, Color { red: 0.2, green: 1.5, blue: 3.0 })
+                    };
+                    world.hitlist.push(
                         Box::new(
                             Sphere::new(
                                 center,
                                 0.2,
-                                Dielectric::new(1.5),
+                                Arc::new(glass),
                             )
                         )
                     );
@@ -131,137 +429,972 @@ pub fn random_scene() -> Scene {
                             Sphere::new(
                                 Vec3::new(0.0, 0.0, 2.0),
                                 2.0,
-                                Dielectric::new(1.5))
+                                Arc::new(Dielectric::new_dispersive(Ior::new_cauchy_from_abbe(1.5, 20.0))))
                             )
                        );
     world.hitlist.push(Box::new(
                             Sphere::new(
                                 Vec3::new(-4.0, 0.0, 2.0),
                                 2.0,
-                                Lambertian::new(Color { red: 0.6, green: 0.2, blue: 0.2 })
+                                Arc::new(Lambertian::new(Color { red: 0.6, green: 0.2, blue: 0.2 }))
                             )
                         ));
     world.hitlist.push(Box::new(
                         Sphere::new(
                             Vec3::new(4.0, 0.0, 2.0),
                             2.0,
-                            Metal::new(
+                            Arc::new(Metal::new(
                                 Color {
                                     red: 0.85,
                                     green: 0.9,
                                     blue: 0.7
                                 },
                                 0.0
-                            )
+                            ))
                         )
                     ));
     world.hitlist.push(Box::new(
                         Plane::new(
                             Vec3::new(1.0, 0.0, 0.0),
                             Vec3::new(1.0, 1.0, 1.0),
-                            Metal::new(
+                            Arc::new(Metal::new(
                                 Color {
                                     red: 0.85,
                                     green: 0.9,
                                     blue: 0.7
                                 },
                                 0.0
-                            )
+                            ))
                         )
                     ));
     world
 }
 
+fn push_sphere<M: Material + Send + Sync + 'static>(
+    world: &mut Scene, row: usize, column: usize, count: usize, material: M
+) {
+    let x = 1.3 * (column as f32 - (count - 1) as f32 / 2.0);
+    let y = 1.6 * row as f32;
+    world.hitlist.push(Box::new(Sphere::new(Vec3::new(x, y, 0.6), 0.6, Arc::new(material))));
+}
+
+/// Tangent-space normal map of `tiles` by `tiles / 2` round dimples.
+fn dimple_normal_map(tiles: usize) -> ImageTexture {
+    let (width, height) = (16 * tiles, 8 * tiles);
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let s = ((x % 16) as f32 + 0.5) / 8.0 - 1.0;
+            let t = 1.0 - ((y % 16) as f32 + 0.5) / 8.0;
+            let r2 = s * s + t * t;
+            let normal = if r2 < 0.64 {
+                Vec3::new(-s, -t, (0.64 - r2).sqrt()).make_unit_vector()
+            } else {
+                Vec3::new(0.0, 0.0, 1.0)
+            };
+            pixels.push(Color::new(0.5 * (normal.x + 1.0), 0.5 * (normal.y + 1.0), 0.5 * (normal.z + 1.0)));
+        }
+    }
+    ImageTexture::new(width, height, pixels)
+}
+
+/// Height map of concentric ripples around the poles.
+fn ripple_height_map(size: usize) -> ImageTexture {
+    let mut pixels = Vec::with_capacity(size * size);
+    for y in 0..size {
+        let height = 0.5 + 0.5 * (40.0 * y as f32 / size as f32).sin();
+        for _x in 0..size {
+            pixels.push(Color::new(height, height, height));
+        }
+    }
+    ImageTexture::new(size, size, pixels)
+}
+
+/// Opacity mask of a picket fence: pointed pickets with a band of half
+/// transparent netting stretched between them.
+fn fence_mask(width: usize, height: usize, pickets: usize) -> ImageTexture {
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        // Height above the bottom of the fence, from 0 to 1.
+        let z = 1.0 - (y as f32 + 0.5) / height as f32;
+        for x in 0..width {
+            let across = (x as f32 + 0.5) / width as f32 * pickets as f32;
+            let offset = (across.fract() - 0.5).abs();
+            let picket = offset < 0.2 && z < 0.85 + 0.75 * (0.2 - offset);
+            let netting = z > 0.3 && z < 0.7;
+            let opacity = if picket { 1.0 } else if netting { 0.5 } else { 0.0 };
+            pixels.push(Color::new(opacity, opacity, opacity));
+        }
+    }
+    ImageTexture::new(width, height, pixels)
+}
+
+/// Spheres showing the materials on a ground plane, in rows from the
+/// front:
+///
+/// - glass: dispersive crown glass, diamond and dense flint, then frosted
+///   and tinted glass.
+/// - principled plastic, gold, brushed steel, car paint with a clearcoat,
+///   velvet with sheen, frosted transmission and an emitter.
+/// - surface detail: bump-mapped plaster and hammered glass, and
+///   normal-mapped gold.
+/// - composites: gold checkered with plastic, varnished wood and a two-sided
+///   shell.
+/// - Lambertian and Oren–Nayar clay, next to a paper lampshade around a small
+///   lamp.
+/// - wax, skin and marble rendered with subsurface scattering.
+/// - thin films: a soap bubble, an oil slick on a dark metal and a lens with
+///   an anti-reflective coating.
+///
+/// A cutout picket fence closes the scene at the back.
+pub fn material_scene() -> Scene {
+    let mut world = Scene::new();
+    let mut ground = Principled::new_textured(Arc::new(CheckerTexture::new(
+        ConstantTexture::new_ref(Color::new(0.2, 0.2, 0.2)),
+        ConstantTexture::new_ref(Color::new(0.8, 0.8, 0.8)),
+        1.0,
+    )));
+    ground.roughness = ConstantTexture::new_f32_ref(0.8);
+    world.hitlist.push(Box::new(Sphere::new(Vec3::new(0.0, 0.0, -1000.0), 1000.0, Arc::new(ground))));
+
+    let glasses = vec![
+        Dielectric::new_dispersive(Ior::new_bk7()),
+        Dielectric::new_dispersive(Ior::new_diamond()),
+        Dielectric::new_dispersive(Ior::new_cauchy_from_abbe(1.7, 20.0)),
+        Dielectric::new_rough(1.5, 0.4, Color::black()),
+        Dielectric::new_rough(1.33, 0.0, Color::new(0.1, 0.8, 1.6)),
+    ];
+    let count = glasses.len();
+    for (column, glass) in glasses.into_iter().enumerate() {
+        push_sphere(&mut world, 0, column, count, glass);
+    }
+
+    let plastic = Principled::new(Color::new(0.8, 0.1, 0.1));
+    let mut gold = Principled::new(Color::new(1.0, 0.78, 0.34));
+    gold.metallic = ConstantTexture::new_f32_ref(1.0);
+    gold.roughness = ConstantTexture::new_f32_ref(0.2);
+    let mut steel = Principled::new(Color::new(0.56, 0.57, 0.58));
+    steel.metallic = ConstantTexture::new_f32_ref(1.0);
+    let mut car_paint = Principled::new(Color::new(0.05, 0.1, 0.5));
+    car_paint.clearcoat = 1.0;
+    let mut velvet = Principled::new(Color::new(0.3, 0.05, 0.3));
+    velvet.roughness = ConstantTexture::new_f32_ref(1.0);
+    velvet.sheen = 1.0;
+    let mut frosted = Principled::new(Color::new(0.9, 1.0, 0.9));
+    frosted.transmission = 1.0;
+    frosted.roughness = ConstantTexture::new_f32_ref(0.3);
+    let mut lamp = Principled::new(Color::black());
+    lamp.emission = ConstantTexture::new_ref(Color::new(1.0, 0.8, 0.5));
+    lamp.emission_strength = 3.0;
+    let principled = vec![plastic, gold, steel, car_paint, velvet, frosted, lamp];
+    let count = principled.len();
+    for (column, material) in principled.into_iter().enumerate() {
+        push_sphere(&mut world, 1, column, count, material);
+    }
+
+    let ripples: Arc<ImageTexture> = Arc::new(ripple_height_map(256));
+    let plaster = BumpMapped::new(Principled::new(Color::new(0.9, 0.9, 0.85)), ripples.clone(), 0.002);
+    push_sphere(&mut world, 2, 0, 3, plaster);
+    let mut gold = Principled::new(Color::new(1.0, 0.78, 0.34));
+    gold.metallic = ConstantTexture::new_f32_ref(1.0);
+    gold.roughness = ConstantTexture::new_f32_ref(0.15);
+    push_sphere(&mut world, 2, 1, 3, NormalMapped::new(gold, Arc::new(dimple_normal_map(12)), 1.0));
+    let hammered = BumpMapped::new(Dielectric::new(1.5), ripples.clone(), 0.004);
+    push_sphere(&mut world, 2, 2, 3, hammered);
+
+    let mut gold = Principled::new(Color::new(1.0, 0.78, 0.34));
+    gold.metallic = ConstantTexture::new_f32_ref(1.0);
+    gold.roughness = ConstantTexture::new_f32_ref(0.2);
+    let checker = Arc::new(CheckerTexture::new(
+        ConstantTexture::new_f32_ref(0.0),
+        ConstantTexture::new_f32_ref(1.0),
+        5.0,
+    ));
+    push_sphere(&mut world, 3, 0, 3, Mix::new(gold, Principled::new(Color::new(0.1, 0.3, 0.1)), checker));
+    let wood = Lambertian::new(Color::new(0.4, 0.2, 0.08));
+    push_sphere(&mut world, 3, 1, 3, Coated::new(wood, 1.5, 0.05, Color::new(0.95, 0.9, 0.8)));
+    let shell = TwoSided::new(
+        Principled::new(Color::new(0.8, 0.8, 0.8)),
+        Lambertian::new(Color::new(0.8, 0.2, 0.1)),
+    );
+    push_sphere(&mut world, 3, 2, 3, shell);
+
+    let clay = Color::new(0.7, 0.45, 0.3);
+    push_sphere(&mut world, 4, 0, 3, Lambertian::new(clay));
+    push_sphere(&mut world, 4, 1, 3, OrenNayar::new(clay, 0.6));
+    push_sphere(&mut world, 4, 2, 3, DiffuseTransmission::new(
+        Color::new(0.5, 0.48, 0.45),
+        Color::new(0.45, 0.4, 0.3),
+    ));
+    let mut bulb = Principled::new(Color::black());
+    bulb.emission = ConstantTexture::new_ref(Color::new(1.0, 0.85, 0.6));
+    bulb.emission_strength = 8.0;
+    world.hitlist.push(Box::new(Sphere::new(Vec3::new(1.3, 6.4, 0.6), 0.2, Arc::new(bulb))));
 
-pub fn print_image() {
-    
-    let x_size = 500;
-    let x_size_f: f32 = 500.0;
-    let y_size = 400;
-    let y_size_f: f32 = 400.0;
-    let s_size =  100; 
-    println!("P3");
-    println!("{} {}", x_size, y_size);
-    println!("255");
-    let random_scene = Arc::new(random_scene());
-
-    let look_from = Vec3::new(20.0 * 0.47f32.cos(), 20.0 * 0.47f32.sin(), 3.0);
-    let look_at = Vec3::new(0.0, 0.0, 1.0);
-    let dist_to_focus = (look_from - look_at).length();
-    let view_up = Vec3::new(0.0, 0.0, 1.0);
-    let aperture: f32 = 0.3;
-    let camera = Arc::new(Camera::new(
-        look_from,
-        look_at,
-        view_up,
-        20.0,
-        x_size_f/y_size_f,
-        aperture,
-        dist_to_focus
+    push_sphere(&mut world, 5, 0, 3, Subsurface::new(Color::new(0.99, 0.9, 0.7), 0.05, 0.0, 1.45));
+    push_sphere(&mut world, 5, 1, 3, Subsurface::new(Color::new(0.98, 0.8, 0.7), 0.1, 0.8, 1.4));
+    push_sphere(&mut world, 5, 2, 3, Coated::new(
+        Subsurface::new(Color::new(0.999, 0.999, 0.995), 0.02, 0.0, 1.5),
+        1.5, 0.0, Color::white(),
     ));
 
-    let mut coordinates = Vec::new();
-    for i in 0..x_size {
-        for j in 0..y_size {
-            coordinates.push((i, j));
+    let mut bubble = Dielectric::new(1.0);
+    let mut soap = ThinFilm::new(800.0, 1.33);
+    soap.thickness_map = Some(ripples);
+    bubble.thin_film = Some(soap);
+    push_sphere(&mut world, 6, 0, 3, bubble);
+    let mut oil_slick = Metal::new(Color::new(0.1, 0.1, 0.1), 0.0);
+    oil_slick.thin_film = Some(ThinFilm::new(450.0, 1.5));
+    push_sphere(&mut world, 6, 1, 3, oil_slick);
+    let mut lens = Dielectric::new(1.5);
+    lens.thin_film = Some(ThinFilm::new(100.0, 1.38));
+    push_sphere(&mut world, 6, 2, 3, lens);
+
+    let fence = AlphaMasked::new(
+        TwoSided::new(Lambertian::new(Color::new(0.9, 0.9, 0.85)), Lambertian::new(Color::new(0.6, 0.6, 0.55))),
+        Arc::new(fence_mask(512, 64, 16)),
+    );
+    world.hitlist.push(Box::new(Quad::new(
+        Vec3::new(-4.8, 11.0, 0.0),
+        Vec3::new(9.6, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 1.2),
+        Arc::new(fence),
+    )));
+    world
+}
+
+/// A rendered image.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels row by row from the top.
+    pub pixels: Vec<ColorU8>,
+    /// Samples taken by each pixel, in the same order.
+    pub sample_counts: Vec<u16>,
+    /// Linear image before post-processing, then the requested AOVs; empty
+    /// without AOVs.
+    pub layers: Vec<Layer>,
+    /// Statistics of the render up to the image.
+    pub statistics: RenderStatistics,
+}
+
+impl Image {
+    /// Plain PPM file of the pixels.
+    pub fn to_ppm(&self) -> String {
+        ppm(self.width, self.height, &self.pixels)
+    }
+}
+
+/// Everything the passes rendered so far have accumulated, from which the
+/// render can go on as if it had not stopped.
+#[derive(Clone)]
+pub struct RenderState {
+    /// Samples per pixel taken so far, fewer where adaptive sampling
+    /// stopped.
+    pub samples: u16,
+    film: Film,
+    aovs: Option<AovFilm>,
+    /// Statistics of the samples of each pixel, row by row from the bottom.
+    statistics: Arc<Vec<PixelStatistics>>,
+    source: SceneSource,
+}
+
+/// Where the scene and camera of a render come from, which a render state
+/// must share with the renderer resuming it.
+#[derive(Clone, Debug, PartialEq)]
+enum SceneSource {
+    /// The built-in scene of settings of this fingerprint, built alike by
+    /// every renderer of them.
+    Settings(String),
+    /// A scene built in code, known to the one renderer given it.
+    Custom(u64),
+}
+
+/// Number of the next renderer of a scene built in code.
+static NEXT_CUSTOM_SCENE: AtomicU64 = AtomicU64::new(0);
+
+impl RenderState {
+    /// State of a render of `settings` from `source` before its first pass.
+    fn new(settings: &RenderSettings, source: SceneSource) -> RenderState {
+        let (x_size, y_size) = (settings.x_size, settings.y_size);
+        let filter = image_filter(settings);
+        RenderState {
+            samples: 0,
+            film: Film::new(0, 0, x_size, y_size, filter),
+            aovs: if settings.aovs.is_empty() && !settings.denoise {
+                None
+            } else {
+                Some(AovFilm::new(x_size, y_size, filter))
+            },
+            statistics: Arc::new(vec![PixelStatistics::default(); (x_size * y_size) as usize]),
+            source,
+        }
+    }
+
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        checkpoint::put_u32(bytes, u32::from(self.samples));
+        self.film.encode(bytes);
+        if let Some(ref aovs) = self.aovs {
+            aovs.encode(bytes);
+        }
+        for statistics in self.statistics.iter() {
+            statistics.encode(bytes);
+        }
+    }
+
+    /// State written by `encode` for a render of the built-in scene of
+    /// `settings`.
+    fn decode(settings: &RenderSettings, decoder: &mut checkpoint::Decoder) -> Result<RenderState, String> {
+        let mut state = RenderState::new(settings, SceneSource::Settings(checkpoint::fingerprint(settings)));
+        state.samples = decoder.u32()?.min(u32::from(settings.s_size)) as u16;
+        state.film.decode(decoder)?;
+        if let Some(ref mut aovs) = state.aovs {
+            aovs.decode(decoder)?;
+        }
+        for statistics in Arc::make_mut(&mut state.statistics).iter_mut() {
+            *statistics = PixelStatistics::decode(decoder)?;
+        }
+        Ok(state)
+    }
+}
+
+/// Samples per pixel of each pass.
+fn pass_samples(settings: &RenderSettings) -> u16 {
+    match (settings.pass_samples, settings.time_limit) {
+        (Some(samples), _) => samples,
+        (None, Some(_)) => settings.min_samples.min(settings.s_size),
+        (None, None) => settings.s_size,
+    }
+}
+
+/// Reconstruction filter of the image.
+fn image_filter(settings: &RenderSettings) -> Filter {
+    Filter::new(
+        settings.filter,
+        settings.filter_radius.unwrap_or_else(|| Filter::default_radius(settings.filter)),
+    )
+}
+
+/// Renders the built-in scene of `settings`. The result only depends on
+/// the settings, whatever the number of threads.
+pub fn render_image(settings: &RenderSettings) -> Result<Image, String> {
+    Ok(Renderer::from_settings(settings)?.render())
+}
+
+/// Renders a scene seen through a camera into images in memory.
+pub struct Renderer {
+    context: Arc<RenderContext>,
+    /// Numbers of the materials of the objects of the scene, for the
+    /// material ID AOV.
+    material_numbers: Vec<u32>,
+    /// Time taken to build the scene, when the renderer did.
+    scene_time: Duration,
+    source: SceneSource,
+}
+
+impl Renderer {
+    /// Renderer of `scene` through `camera` with `settings`, whose scene
+    /// kind is then left unused. The camera should have the aspect ratio
+    /// of the image. Only this renderer can resume its render states, as
+    /// nothing tells whether another scene is the same. Fails if the
+    /// settings do not validate.
+    pub fn new<C: Camera + Send + Sync + 'static>(
+        scene: Scene, camera: C, settings: RenderSettings,
+    ) -> Result<Renderer, String> {
+        settings.validate()?;
+        let (x_size, y_size) = (settings.x_size, settings.y_size);
+        let mut tiles = Vec::new();
+        for y0 in (0..y_size).step_by(TILE_SIZE as usize) {
+            for x0 in (0..x_size).step_by(TILE_SIZE as usize) {
+                tiles.push((x0, y0, (x0 + TILE_SIZE).min(x_size), (y0 + TILE_SIZE).min(y_size)));
+            }
         }
+        Ok(Renderer {
+            material_numbers: scene.material_numbers(),
+            context: Arc::new(RenderContext { settings, camera: Box::new(camera), scene, tiles }),
+            scene_time: Duration::default(),
+            source: SceneSource::Custom(NEXT_CUSTOM_SCENE.fetch_add(1, Ordering::Relaxed)),
+        })
+    }
+
+    /// Renderer of the built-in scene of `settings`, seen from its usual
+    /// point of view through the camera of the settings. Fails if the
+    /// settings do not validate.
+    pub fn from_settings(settings: &RenderSettings) -> Result<Renderer, String> {
+        settings.validate()?;
+        let started = Instant::now();
+        // The material scene is framed to hold all its rows.
+        let (scene, look_from, look_at) = match settings.scene {
+            SceneKind::Random => (
+                random_scene(settings.seed),
+                Vec3::new(20.0 * 0.47f32.cos(), 20.0 * 0.47f32.sin(), 3.0),
+                Vec3::new(0.0, 0.0, 1.0),
+            ),
+            SceneKind::Materials => (
+                material_scene(),
+                Vec3::new(0.0, -16.0, 17.0),
+                Vec3::new(0.0, 4.4, 0.6),
+            ),
+        };
+        let dist_to_focus = (look_from - look_at).length();
+        let view_up = Vec3::new(0.0, 0.0, 1.0);
+        let mut aspect = settings.x_size as f32 / settings.y_size as f32;
+        let vertical_fov: f32 = 20.0;
+        let aperture: f32 = 0.3;
+        let convergence = settings.convergence.unwrap_or(dist_to_focus);
+        let camera: Box<dyn Camera + Send + Sync> = match settings.camera {
+            CameraKind::Perspective => {
+                if matches!(settings.stereo, Some(StereoKind::Parallel | StereoKind::ToeIn)) {
+                    // Each eye gets half of the width.
+                    aspect /= 2.0;
+                }
+                let mut camera = match settings.focal_length {
+                    Some(focal_length) => {
+                        let lens = Lens { focal_length, sensor: settings.sensor, f_number: settings.f_number };
+                        PerspectiveCamera::physical(look_from, look_at, view_up, aspect, dist_to_focus, &lens)
+                    }
+                    None => PerspectiveCamera::new(
+                        look_from, look_at, view_up, vertical_fov, aspect, aperture, dist_to_focus,
+                    ),
+                };
+                camera.aperture = Aperture {
+                    blades: settings.blades,
+                    rotation: settings.blade_rotation,
+                    vignetting: settings.vignetting,
+                    cat_eye: settings.cat_eye,
+                };
+                let (interocular, layout) = (settings.interocular, StereoLayout::SideBySide);
+                match settings.stereo {
+                    Some(StereoKind::Parallel) => Box::new(StereoCamera::parallel(&camera, interocular, convergence, layout)),
+                    Some(StereoKind::ToeIn) => Box::new(StereoCamera::toe_in(&camera, interocular, convergence, layout)),
+                    _ => Box::new(camera),
+                }
+            }
+            // As much of the scene around `look_at` as in perspective.
+            CameraKind::Orthographic => {
+                let height = 2.0 * dist_to_focus * (vertical_fov.to_radians() / 2.0).tan();
+                Box::new(OrthographicCamera::new(look_from, look_at, view_up, height, aspect))
+            }
+            CameraKind::Fisheye => Box::new(FisheyeCamera::new(look_from, look_at, view_up, 180.0, aspect)),
+            CameraKind::Equirectangular => {
+                let camera = EquirectangularCamera::new(look_from, look_at, view_up);
+                match settings.stereo {
+                    Some(StereoKind::Omnidirectional) => {
+                        Box::new(StereoCamera::omnidirectional(&camera, settings.interocular))
+                    }
+                    _ => Box::new(camera),
+                }
+            }
+        };
+        let mut renderer = Renderer::new(scene, camera, settings.clone())?;
+        renderer.scene_time = started.elapsed();
+        renderer.source = SceneSource::Settings(checkpoint::fingerprint(settings));
+        Ok(renderer)
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.context.settings
+    }
+
+    /// Renders the whole image, within the time limit of the settings if
+    /// any.
+    pub fn render(&self) -> Image {
+        let controller = RenderController::new(self.settings());
+        self.render_from(self.new_state(), &controller, |_, _| {}, |_| {})
+    }
+
+    fn new_state(&self) -> RenderState {
+        RenderState::new(self.settings(), self.source.clone())
+    }
+
+    /// Renders the image in passes of `pass_samples` samples per pixel,
+    /// handing the image so far and the state of the render to `on_pass`
+    /// after each pass, and how far the render has gone to `on_progress`
+    /// after each tile. The render goes on from `resume` if given, a state
+    /// of an earlier render of the same scene and settings, to the very
+    /// image it would have finished with. The last image is returned, early
+    /// if `controller` stops the render: a pass stopped halfway keeps the
+    /// samples of the tiles it finished, each pixel still averaging its own
+    /// samples, but is not handed to `on_pass`, so that the states given
+    /// there can always be resumed. Fails if `resume` is a state of another
+    /// scene or settings: states of the built-in scenes resume on any
+    /// renderer of the same settings, states of a scene built in code only
+    /// on the renderer that rendered them.
+    pub fn render_progressive<F: FnMut(&Image, &RenderState), P: FnMut(&Progress)>(
+        &self, resume: Option<RenderState>, controller: &RenderController, on_pass: F, on_progress: P,
+    ) -> Result<Image, String> {
+        let state = match resume {
+            Some(state) if state.source != self.source => {
+                return Err(String::from("the render state is of another scene"));
+            }
+            Some(state) => state,
+            None => self.new_state(),
+        };
+        Ok(self.render_from(state, controller, on_pass, on_progress))
     }
-    let size_coordinates = coordinates.len();
-    let size_slice = size_coordinates / 8;
-    let rest = size_coordinates % 8;
-    let mut comp = Vec::new() ;
-    for _num_thread in 0..8 {
-        comp.push(Mutex::new(HashMap::new()));
+
+    /// `render_progressive` from `state`, a state of this renderer.
+    fn render_from<F: FnMut(&Image, &RenderState), P: FnMut(&Progress)>(
+        &self, mut state: RenderState, controller: &RenderController, mut on_pass: F, mut on_progress: P,
+    ) -> Image {
+        let context = &self.context;
+        let settings = &context.settings;
+        let mut statistics = RenderStatistics { scene_time: self.scene_time, ..RenderStatistics::default() };
+        let finish = |state: &RenderState, statistics: &mut RenderStatistics| {
+            let finish_start = Instant::now();
+            let mut image = finish_image(settings, state, &self.material_numbers);
+            statistics.finish_time += finish_start.elapsed();
+            image.statistics = *statistics;
+            image
+        };
+
+        let pass_samples = pass_samples(settings);
+        let resumed = state.samples;
+        let render_start = Instant::now();
+        let mut image = None;
+        while state.samples < settings.s_size && !controller.should_stop() {
+            let (start, end) = (state.samples, state.samples.saturating_add(pass_samples).min(settings.s_size));
+            let pass_start = Instant::now();
+            let mut progress = Progress {
+                pass: start..end,
+                s_size: settings.s_size,
+                start: resumed,
+                tiles_done: 0,
+                tiles: context.tiles.len(),
+                samples: statistics.rays.samples,
+                elapsed: render_start.elapsed(),
+            };
+            let rendered_tiles = render_pass(context, start..end, &state.statistics, controller, |rendered| {
+                progress.tiles_done += 1;
+                progress.samples += rendered.rays.samples;
+                progress.elapsed = render_start.elapsed();
+                on_progress(&progress);
+            });
+            let complete = rendered_tiles.len() == context.tiles.len();
+            // Tiles are merged in a fixed order, so that the sums of the
+            // pixels they share do not depend on the threads.
+            let image_statistics = Arc::make_mut(&mut state.statistics);
+            for rendered in rendered_tiles {
+                state.film.merge(&rendered.film);
+                if let (Some(aovs), Some(tile_aovs)) = (state.aovs.as_mut(), rendered.aovs.as_ref()) {
+                    aovs.merge(tile_aovs);
+                }
+                let (x0, y0, x1, _) = rendered.bounds;
+                for (index, pixel_statistics) in rendered.statistics.iter().enumerate() {
+                    let (i, j) = (x0 + index as u32 % (x1 - x0), y0 + index as u32 / (x1 - x0));
+                    image_statistics[(j * settings.x_size + i) as usize] = *pixel_statistics;
+                }
+                statistics.rays += rendered.rays;
+            }
+            statistics.render_time += pass_start.elapsed();
+            if !complete {
+                return finish(&state, &mut statistics);
+            }
+            state.samples = end;
+            let current = finish(&state, &mut statistics);
+            on_pass(&current, &state);
+            image = Some(current);
+        }
+        // Stopped before the first pass, or resumed with no pass left.
+        image.unwrap_or_else(|| finish(&state, &mut statistics))
     }
-    let computation = Arc::new(comp);
-    let share_coordinates = Arc::new(coordinates);
+}
+
+/// What the threads rendering a pass share.
+struct RenderContext {
+    settings: RenderSettings,
+    camera: Box<dyn Camera + Send + Sync>,
+    scene: Scene,
+    tiles: Vec<TileBounds>,
+}
+
+/// Renders the given samples of every pixel, returning the tiles in order
+/// and handing each to `on_tile` as soon as it is rendered. Once
+/// `controller` stops the render, the tiles not started yet are left out.
+fn render_pass<F: FnMut(&RenderedTile)>(
+    context: &Arc<RenderContext>,
+    samples: Range<u16>,
+    statistics: &Arc<Vec<PixelStatistics>>,
+    controller: &RenderController,
+    mut on_tile: F,
+) -> Vec<RenderedTile> {
+    let filter = image_filter(&context.settings);
+    let post = PostProcess::new(&context.settings);
+    let next_tile = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = mpsc::channel();
     let mut handles = vec![];
-    for num_thread in 0..8 {
-        let computation = Arc::clone(&computation);
-        let coordinates = Arc::clone(&share_coordinates);
-        let camera = Arc::clone(&camera);
-        let random_scene = Arc::clone(&random_scene);
+    for _num_thread in 0..context.settings.threads {
+        let context = Arc::clone(context);
+        let next_tile = Arc::clone(&next_tile);
+        let sender = sender.clone();
+        let statistics = Arc::clone(statistics);
+        let controller = controller.clone();
+        let samples = samples.clone();
         let handle = thread::spawn(move || {
-            let mut map = computation[num_thread].lock().unwrap();
-            let start = num_thread * size_slice;
-            let mut end = (num_thread + 1) * size_slice - 1;
-            if num_thread == 7 {
-                end += rest;
-            }
-            // println!("n{} s{} e{} l{}", num_thread, start, end, size_coordinates);
-            for (i, j) in &coordinates[start..end+1] {
-                map.insert((*i, *j), 
-                    render_color(
-                        s_size, 
-                        *i as f32,
-                        *j as f32,
-                        x_size as f32,
-                        y_size as f32,
-                        &camera,
-                        &random_scene,
-                    )
-                );
+            let settings = &context.settings;
+            let mut sampler = sampler::create_sampler(settings.sampler, u32::from(settings.s_size), settings.seed);
+            while !controller.should_stop() {
+                let tile = next_tile.fetch_add(1, Ordering::Relaxed);
+                let bounds = match context.tiles.get(tile) {
+                    Some(bounds) => *bounds,
+                    None => break,
+                };
+                let mut rendered = RenderedTile::new(bounds, samples.clone(), settings, filter, &statistics);
+                let (x0, y0, x1, y1) = bounds;
+                for j in y0..y1 {
+                    for i in x0..x1 {
+                        let (camera, scene) = (&context.camera, &context.scene);
+                        render_pixel(settings, &post, (i, j), camera, scene, sampler.as_mut(), &mut rendered);
+                    }
+                }
+                sender.send((tile, rendered)).unwrap();
             }
         });
         handles.push(handle)
     }
+    // The receiver runs out once every thread is done with its sender.
+    drop(sender);
+    let mut rendered_tiles = vec![None; context.tiles.len()];
+    for (tile, rendered) in receiver {
+        on_tile(&rendered);
+        rendered_tiles[tile] = Some(rendered);
+    }
     for handle in handles {
         handle.join().unwrap();
     }
-    let mut map = HashMap::new();
-    for num_thread in 0..8 {
-        let current_map = computation[num_thread].lock().unwrap().clone().into_iter();
-        map.extend(
-            current_map.map(|(k, v)| (k.clone(), v.clone()))
-        );
+    rendered_tiles.into_iter().flatten().collect()
+}
+
+/// Image of the samples accumulated so far, with the materials of the
+/// scene numbered by `material_numbers` for the material ID AOV.
+fn finish_image(settings: &RenderSettings, state: &RenderState, material_numbers: &[u32]) -> Image {
+    let statistics = &state.statistics;
+    let denoised;
+    let film = if let (true, Some(aovs)) = (settings.denoise, &state.aovs) {
+        // The variance of the mean is what is left as noise.
+        let variances: Vec<_> = statistics.iter()
+            .map(|statistics| statistics.variance() / statistics.count.max(1) as f32)
+            .collect();
+        denoised = denoise::denoise(&state.film, aovs, &variances);
+        &denoised
+    } else {
+        &state.film
+    };
+    let pixels = film.to_pixels(&PostProcess::new(settings));
+    let mut sample_counts = Vec::with_capacity(statistics.len());
+    for row in statistics.chunks(settings.x_size as usize).rev() {
+        sample_counts.extend(row.iter().map(|statistics| statistics.count as u16));
+    }
+    let mut layers = Vec::new();
+    if let (false, Some(aovs)) = (settings.aovs.is_empty(), &state.aovs) {
+        layers.push(Layer::from_film("beauty", film));
+        layers.extend(settings.aovs.iter().map(|kind| aovs.layer(*kind, material_numbers)));
+    }
+    Image {
+        width: settings.x_size,
+        height: settings.y_size,
+        pixels,
+        sample_counts,
+        layers,
+        statistics: RenderStatistics::default(),
+    }
+}
+
+/// Plain PPM of the given pixels, row by row from the top.
+fn ppm(width: u32, height: u32, pixels: &[ColorU8]) -> String {
+    let mut ppm = format!("P3\n{} {}\n255\n", width, height);
+    for color in pixels {
+        ppm += &format!("{} {} {}\n", color.red, color.green, color.blue);
+    }
+    ppm
+}
+
+/// Prints the image as a PPM on the standard output, and writes the
+/// snapshots and checkpoints of the passes, the sample count heatmap and
+/// the AOVs if requested. With `resume`, the render goes on from the
+/// checkpoint. An image stopped by `controller` is output all the same.
+/// Unless `quiet`, a progress bar and then the statistics of the render are
+/// shown on the standard error.
+pub fn print_image(settings: &RenderSettings, controller: &RenderController) -> io::Result<()> {
+    let (width, height) = (settings.x_size, settings.y_size);
+    let resume = match settings.checkpoint {
+        Some(ref path) if settings.resume => Some(checkpoint::load(path, settings)?),
+        _ => None,
+    };
+    let mut pass_result = Ok(());
+    let mut output_time = Duration::default();
+    let on_pass = |image: &Image, state: &RenderState| {
+        if pass_result.is_err() {
+            return;
+        }
+        let output_start = Instant::now();
+        if let Some(ref path) = settings.snapshot {
+            // The snapshot is replaced at once, so that it can be viewed
+            // while the render goes on.
+            let partial = format!("{}.partial", path);
+            pass_result = fs::write(&partial, image.to_ppm())
+                .and_then(|()| fs::rename(&partial, path));
+        }
+        if let (Some(path), Ok(())) = (&settings.checkpoint, &pass_result) {
+            pass_result = checkpoint::save(path, settings, state);
+        }
+        output_time += output_start.elapsed();
+    };
+    let on_progress = |progress: &Progress| {
+        if !settings.quiet {
+            eprint!("\r{}", progress);
+        }
+    };
+    let renderer = Renderer::from_settings(settings)
+        .map_err(|message| io::Error::new(io::ErrorKind::InvalidInput, message))?;
+    let image = renderer.render_progressive(resume, controller, on_pass, on_progress)
+        .map_err(|message| io::Error::new(io::ErrorKind::InvalidInput, message))?;
+    if !settings.quiet {
+        eprintln!();
+    }
+    pass_result?;
+    let output_start = Instant::now();
+    print!("{}", image.to_ppm());
+    if let Some(ref path) = settings.heatmap {
+        let min = settings.min_samples.min(settings.s_size);
+        let heatmap: Vec<_> = image.sample_counts.iter().map(|count| {
+            ColorU8::make_from_color(f32::from(u8::MAX) * adaptive::heatmap_color(*count, min, settings.s_size))
+        }).collect();
+        fs::write(path, ppm(width, height, &heatmap))?;
+    }
+    if let Some(ref path) = settings.aov_output {
+        if path.ends_with(".exr") {
+            // The image is the default layer of a multi-layer file.
+            let channels: Vec<_> = image.layers.iter()
+                .flat_map(|layer| layer.exr_channels(layer.name != "beauty"))
+                .collect();
+            exr::write(path, width, height, &channels)?;
+        } else {
+            for layer in &image.layers[1..] {
+                exr::write(&format!("{}_{}.exr", path, layer.name), width, height, &layer.exr_channels(false))?;
+            }
+        }
+    }
+    if !settings.quiet {
+        let statistics = RenderStatistics { output_time: output_time + output_start.elapsed(), ..image.statistics };
+        eprintln!("{}", statistics);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_settings() -> RenderSettings {
+        RenderSettings {
+            x_size: 12,
+            y_size: 8,
+            s_size: 4,
+            threads: 1,
+            seed: 7,
+            ..RenderSettings::default()
+        }
+    }
+
+    #[test]
+    fn test_render_is_reproducible() {
+        // Wide enough for tiles to share the pixels along their edges.
+        let settings = RenderSettings { x_size: 36, y_size: 20, ..small_settings() };
+        let image = render_image(&settings).unwrap().pixels;
+        assert_eq!(image, render_image(&settings).unwrap().pixels);
+        let threaded = RenderSettings { threads: 3, ..settings.clone() };
+        assert_eq!(image, render_image(&threaded).unwrap().pixels);
+        let reseeded = RenderSettings { seed: 8, ..settings };
+        assert_ne!(image, render_image(&reseeded).unwrap().pixels);
+    }
+    #[test]
+    fn test_progressive_rendering() {
+        let settings = RenderSettings {
+            s_size: 32,
+            adaptive_threshold: Some(0.02),
+            min_samples: 4,
+            ..small_settings()
+        };
+        let image = render_image(&settings).unwrap();
+        let progressive = RenderSettings { pass_samples: Some(6), ..settings };
+        let mut passes = Vec::new();
+        let mut reports = Vec::new();
+        let last = Renderer::from_settings(&progressive).unwrap().render_progressive(
+            None, &RenderController::new(&progressive),
+            |image, state| passes.push((image.pixels.clone(), state.samples)),
+            |progress| reports.push(progress.clone()),
+        ).unwrap();
+        assert_eq!(passes.iter().map(|(_, samples)| *samples).collect::<Vec<_>>(), [6, 12, 18, 24, 30, 32]);
+        // The single tile of each pass is reported, the last one with every
+        // sample.
+        assert_eq!(reports.len(), 6);
+        assert!(reports.iter().all(|progress| progress.tiles_done == progress.tiles));
+        assert_eq!(reports.last().unwrap().fraction(), 1.0);
+        let samples: u64 = last.sample_counts.iter().map(|count| u64::from(*count)).sum();
+        assert_eq!(reports.last().unwrap().samples, samples);
+        let rays = last.statistics.rays;
+        assert_eq!((rays.samples, rays.primary, rays.shadow), (samples, samples, 0));
+        assert!(rays.average_path_length() > 1.0);
+        assert_eq!(passes.last().unwrap().0, last.pixels);
+        // Passes take the same samples, only added up in another order.
+        assert_eq!(last.sample_counts, image.sample_counts);
+        for (a, b) in last.pixels.iter().zip(image.pixels.iter()) {
+            assert!((i32::from(a.green) - i32::from(b.green)).abs() <= 1);
+        }
+        assert_ne!(passes[0].0, last.pixels);
+        // Settings built in code are checked like the command line's.
+        assert!(render_image(&RenderSettings { pass_samples: Some(0), ..small_settings() }).is_err());
+        assert!(render_image(&RenderSettings { s_size: 0, ..small_settings() }).is_err());
+        assert!(render_image(&RenderSettings { x_size: 0, ..small_settings() }).is_err());
+        let adaptive = RenderSettings { adaptive_threshold: Some(0.02), min_samples: 0, ..small_settings() };
+        assert!(render_image(&adaptive).is_err());
     }
-    // println!("{}", map.len());
-    for j in (0..y_size).rev() {
-        for i in 0..x_size {
-            match map.get(&(i,j)) {
-                Some(color) => println!("{} {} {}", color.red, color.blue, color.green),
-                None => println!("{} {} not found.", i, j)
+    #[test]
+    fn test_resume() {
+        let settings = RenderSettings {
+            s_size: 16,
+            adaptive_threshold: Some(0.02),
+            min_samples: 4,
+            pass_samples: Some(4),
+            aovs: vec![AovKind::Albedo, AovKind::ObjectId, AovKind::MaterialId],
+            aov_output: Some(String::from("passes.exr")),
+            ..small_settings()
+        };
+        let controller = RenderController::new(&settings);
+        let mut states = Vec::new();
+        let renderer = Renderer::from_settings(&settings).unwrap();
+        let on_pass = |_: &Image, state: &RenderState| states.push(state.clone());
+        let last = renderer.render_progressive(None, &controller, on_pass, |_| {}).unwrap();
+        // Resuming from a saved pass gives the image of the render that went
+        // on, to the bit.
+        let bytes = checkpoint::encode(&settings, &states[1]);
+        let state = checkpoint::decode(&settings, &bytes).unwrap();
+        assert_eq!(state.samples, 8);
+        let mut samples = Vec::new();
+        let resumed = renderer.render_progressive(
+            Some(state), &controller, |_, state| samples.push(state.samples), |_| {},
+        ).unwrap();
+        assert_eq!(samples, [12, 16]);
+        assert_eq!(resumed.pixels, last.pixels);
+        assert_eq!(resumed.sample_counts, last.sample_counts);
+        for (a, b) in resumed.layers.iter().zip(last.layers.iter()) {
+            assert_eq!(a.channels, b.channels);
+        }
+        // There is nothing left to render from the last pass.
+        let finished = renderer.render_progressive(
+            states.pop(), &controller, |_, _| panic!("rendered a pass"), |_| {},
+        ).unwrap();
+        assert_eq!(finished.pixels, last.pixels);
+    }
+    #[test]
+    fn test_resume_other_scene() {
+        let settings = RenderSettings { s_size: 2, pass_samples: Some(1), ..small_settings() };
+        let controller = RenderController::new(&settings);
+        let custom = || {
+            let from_settings = Renderer::from_settings(&settings).unwrap();
+            let context = Arc::try_unwrap(from_settings.context).ok().unwrap();
+            Renderer::new(context.scene, context.camera, settings.clone()).unwrap()
+        };
+        let mut states = Vec::new();
+        let renderer = custom();
+        renderer.render_progressive(None, &controller, |_, state| states.push(state.clone()), |_| {}).unwrap();
+        assert!(renderer.render_progressive(states.first().cloned(), &controller, |_, _| {}, |_| {}).is_ok());
+        // Even the same scene built anew may differ, for all the renderer
+        // knows.
+        assert!(custom().render_progressive(states.pop(), &controller, |_, _| {}, |_| {}).is_err());
+    }
+    #[test]
+    fn test_cancel_render() {
+        let settings = RenderSettings { s_size: 16, pass_samples: Some(4), ..small_settings() };
+        let controller = RenderController::new(&settings);
+        let mut passes = Vec::new();
+        let renderer = Renderer::from_settings(&settings).unwrap();
+        let image = renderer.render_progressive(None, &controller, |image, _| {
+            passes.push(image.pixels.clone());
+            controller.cancel();
+        }, |_| {}).unwrap();
+        // The render stops with the image of the passes it finished.
+        assert_eq!(passes, [image.pixels]);
+        assert!(image.sample_counts.iter().all(|count| *count == 4));
+
+        let cancelled = RenderController::new(&settings);
+        cancelled.cancel();
+        let image = renderer.render_progressive(None, &cancelled, |_, _| panic!("rendered a pass"), |_| {}).unwrap();
+        assert_eq!(image.pixels.len(), 12 * 8);
+        assert!(image.sample_counts.iter().all(|count| *count == 0));
+    }
+    #[test]
+    fn test_render_aovs() {
+        let settings = RenderSettings {
+            aovs: vec![AovKind::Direct, AovKind::Indirect, AovKind::ObjectId],
+            aov_output: Some(String::from("passes.exr")),
+            ..small_settings()
+        };
+        let image = render_image(&settings).unwrap();
+        assert!(render_image(&small_settings()).unwrap().layers.is_empty());
+        let names: Vec<_> = image.layers.iter().map(|layer| layer.name).collect();
+        assert_eq!(names, ["beauty", "direct", "indirect", "object-id"]);
+        assert!(image.layers[3].channels[0].iter().any(|id| *id > 0.0));
+        // The light passes add up to the image, even where the lens lets
+        // less light through.
+        let vignetted = RenderSettings { focal_length: Some(35.0), vignetting: 1.0, cat_eye: 0.5, ..settings.clone() };
+        for image in [image, render_image(&vignetted).unwrap()] {
+            let (beauty, direct, indirect) = (&image.layers[0], &image.layers[1], &image.layers[2]);
+            for channel in 0..3 {
+                for index in 0..beauty.channels[channel].len() {
+                    let sum = direct.channels[channel][index] + indirect.channels[channel][index];
+                    assert!((sum - beauty.channels[channel][index]).abs() < 1e-4);
+                }
             }
         }
     }
+    #[test]
+    fn test_adaptive_sampling() {
+        let camera = PerspectiveCamera::new(
+            Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0),
+            20.0, 1.0, 0.0, 1.0,
+        );
+        let settings = RenderSettings {
+            s_size: 64,
+            adaptive_threshold: Some(0.01),
+            min_samples: 8,
+            ..small_settings()
+        };
+        let mut sampler = sampler::create_sampler(settings.sampler, 64, 0);
+        let statistics = vec![PixelStatistics::default(); 12 * 8];
+        let filter = Filter::new(FilterKind::Box, 0.5);
+        let post = PostProcess::new(&settings);
+        let mut tile = RenderedTile::new((0, 0, 12, 8), 0..64, &settings, filter, &statistics);
+        // The sky barely changes within a pixel.
+        let sky = Scene::new();
+        assert_eq!(render_pixel(&settings, &post, (3, 3), &camera, &sky, sampler.as_mut(), &mut tile).count, 8);
+        // The edge of a black sphere crosses the pixel.
+        let mut edge = Scene::new();
+        edge.hitlist.push(Box::new(Sphere::new(
+            Vec3::new(-1.72, 10.0, -0.2), 1.0, Arc::new(Lambertian::new(Color::black())),
+        )));
+        let mut tile = RenderedTile::new((0, 0, 12, 8), 0..64, &settings, filter, &statistics);
+        let count = render_pixel(&settings, &post, (3, 3), &camera, &edge, sampler.as_mut(), &mut tile).count;
+        assert!(count > 8);
+    }
+
+    #[test]
+    fn test_blocked_rays() {
+        let camera = FisheyeCamera::new(
+            Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 180.0, 1.5,
+        );
+        let settings = small_settings();
+        let mut sampler = sampler::create_sampler(settings.sampler, 4, 0);
+        let statistics = vec![PixelStatistics::default(); 12 * 8];
+        let filter = Filter::new(FilterKind::Box, 0.5);
+        let post = PostProcess::new(&settings);
+        let mut tile = RenderedTile::new((0, 0, 12, 8), 0..4, &settings, filter, &statistics);
+        // The corner is outside the image circle: none of its rays is traced.
+        let corner = render_pixel(&settings, &post, (0, 0), &camera, &Scene::new(), sampler.as_mut(), &mut tile);
+        assert_eq!((corner.count, corner.variance()), (4, 0.0));
+        assert_eq!((tile.rays.samples, tile.rays.primary), (4, 0));
+        render_pixel(&settings, &post, (6, 4), &camera, &Scene::new(), sampler.as_mut(), &mut tile);
+        assert_eq!((tile.rays.samples, tile.rays.primary), (8, 4));
+    }
 }
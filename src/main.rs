@@ -1,5 +1,28 @@
-use raytracer; 
+use raytracer::{RenderController, RenderSettings};
 
 fn main() {
-    raytracer::print_image();
+    let settings = match RenderSettings::from_args(std::env::args().skip(1)) {
+        Ok(settings) => settings,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+    let controller = RenderController::new(&settings);
+    let handle = controller.clone();
+    // The first Ctrl-C stops the render and outputs the image so far, the
+    // next one quits at once.
+    let handler = ctrlc::set_handler(move || {
+        if handle.is_cancelled() {
+            std::process::exit(130);
+        }
+        handle.cancel();
+    });
+    if let Err(error) = handler {
+        eprintln!("{}", error);
+    }
+    if let Err(error) = raytracer::print_image(&settings, &controller) {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
 }
@@ -1,24 +1,104 @@
-use rand::prelude::*;
+use std::sync::Arc;
 
 use crate::vec3::Vec3;
 use crate::color::Color;
 use crate::ray::Ray;
+use crate::texture::{TextureRef, ConstantTexture};
+use crate::medium::Medium;
+use crate::spectrum::rgb_to_spectrum;
+use crate::sampler::Sampler;
 
 #[derive(Clone, Copy)]
 pub struct HitRecord<'a> {
     pub t_factor: f32,
     pub p_vect: Vec3,
     pub normal: Vec3,
-    pub material: &'a Material,
+    /// Unit vector along the surface in the direction of increasing `u`.
+    pub tangent: Vec3,
+    /// Surface texture coordinates.
+    pub u: f32,
+    pub v: f32,
+    /// Whether the ray arrived on the side `normal` points to.
+    pub front_face: bool,
+    pub material: &'a dyn Material,
+}
+
+/// Kind of scattering event, by which the light of a path is split into
+/// per-lobe contributions.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Lobe {
+    Diffuse,
+    Specular,
+    /// Any scattering through the surface.
+    Transmission,
 }
 
 pub struct Scatter {
     pub color: Color,
     pub ray: Option<Ray>,
+    /// Lobe the ray was scattered into, `None` when it was absorbed.
+    pub lobe: Option<Lobe>,
 }
 
 pub trait Material {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<Scatter>;
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter>;
+
+    /// Light emitted by the surface at the hit point.
+    fn emitted(&self, _hit_record: &HitRecord) -> Color {
+        Color::black()
+    }
+
+    /// Whether scattering depends on `Ray::wavelength`, in which case a
+    /// spectral path can only carry a single wavelength.
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+
+    /// Medium filling the closed surface, entered by rays scattered to the
+    /// side opposite to the normal.
+    fn interior(&self) -> Option<Medium> {
+        None
+    }
+
+    /// Probability that a ray is stopped at the hit point; rays pass
+    /// through the surface otherwise, see `hitable::hit_opaque`.
+    fn opacity(&self, _hit_record: &HitRecord) -> f32 {
+        1.0
+    }
+
+    /// Address of the material, the same for the objects sharing it.
+    fn identity(&self) -> *const () {
+        self as *const Self as *const ()
+    }
+}
+
+/// A material shared between objects and threads.
+pub type MaterialRef = Arc<dyn Material + Send + Sync>;
+
+impl<M: Material + ?Sized> Material for Arc<M> {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        (**self).scatter(ray, hit_record, sampler)
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Color {
+        (**self).emitted(hit_record)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        (**self).is_dispersive()
+    }
+
+    fn interior(&self) -> Option<Medium> {
+        (**self).interior()
+    }
+
+    fn opacity(&self, hit_record: &HitRecord) -> f32 {
+        (**self).opacity(hit_record)
+    }
+
+    fn identity(&self) -> *const () {
+        (**self).identity()
+    }
 }
 
 #[derive(Clone)]
@@ -32,27 +112,145 @@ impl Lambertian {
     }
 }
 
-fn random_in_unit_sphere() -> Vec3 {
-    let mut rng = thread_rng();
-    loop {
-        let p = (2.0 * 
-            Vec3::new(
-                rng.gen_range(0.0, 1.0),
-                rng.gen_range(0.0, 1.0),
-                rng.gen_range(0.0, 1.0)
-            )) -  Vec3::new_unit();
-        if p.square_length() < 1.0 {
-            return p;
-        }
+/// Surface emitting `emit` on both sides, and absorbing the light reaching
+/// it.
+#[derive(Clone)]
+pub struct DiffuseLight {
+    pub emit: Color,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> DiffuseLight {
+        DiffuseLight { emit }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _ray: &Ray, _hit_record: &HitRecord, _sampler: &mut dyn Sampler) -> Option<Scatter> {
+        None
+    }
+
+    fn emitted(&self, _hit_record: &HitRecord) -> Color {
+        self.emit
     }
 }
 
+/// Uniformly distributed point of the unit ball.
+fn random_in_unit_sphere(sampler: &mut dyn Sampler) -> Vec3 {
+    let (u, v) = sampler.get_2d();
+    let radius = sampler.get_1d().cbrt();
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * v;
+    radius * Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Direction of the hemisphere around the unit vector `normal`, with a
+/// density proportional to its cosine with `normal` (Malley's method).
+fn random_cosine_direction(normal: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+    let (r2, u) = sampler.get_2d();
+    let phi = 2.0 * std::f32::consts::PI * u;
+    let r = r2.sqrt();
+    let (tangent, bitangent) = normal.orthonormal_basis();
+    r * phi.cos() * tangent + r * phi.sin() * bitangent + (1.0 - r2).max(0.0).sqrt() * *normal
+}
+
 impl Material for Lambertian {
-    fn scatter(&self, _ray: &Ray, hit_record: &HitRecord) -> Option<Scatter> {
-        let target = hit_record.p_vect + hit_record.normal + random_in_unit_sphere();
+    fn scatter(&self, _ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
         Some(Scatter{
             color: self.albedo,
-            ray: Some(Ray::new(hit_record.p_vect, target - hit_record.p_vect)),
+            ray: Some(Ray::new(hit_record.p_vect, random_cosine_direction(&hit_record.normal, sampler))),
+            lobe: Some(Lobe::Diffuse),
+        })
+    }
+}
+
+/// Rough diffuse reflection of Oren and Nayar (1994), in its qualitative
+/// form. `sigma` is the standard deviation of the facet slopes, in radians;
+/// `0.0` gives back the Lambertian.
+#[derive(Clone)]
+pub struct OrenNayar {
+    pub albedo: Color,
+    pub sigma: f32,
+}
+
+impl OrenNayar {
+    pub fn new(albedo: Color, sigma: f32) -> OrenNayar {
+        OrenNayar { albedo, sigma }
+    }
+
+    /// Ratio of the Oren–Nayar BRDF to the Lambertian one between the unit
+    /// directions `incoming` and `outgoing`.
+    fn factor(&self, normal: &Vec3, incoming: &Vec3, outgoing: &Vec3) -> f32 {
+        let sigma2 = self.sigma * self.sigma;
+        let a = 1.0 - 0.5 * sigma2 / (sigma2 + 0.33);
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+        let cos_i = incoming.dot(*normal).clamp(-1.0, 1.0);
+        let cos_o = outgoing.dot(*normal).clamp(-1.0, 1.0);
+        let sin_i = (1.0 - cos_i * cos_i).max(0.0).sqrt();
+        let sin_o = (1.0 - cos_o * cos_o).max(0.0).sqrt();
+        let mut cos_phi = 0.0;
+        if sin_i > 1e-4 && sin_o > 1e-4 {
+            let projected_i = (*incoming - cos_i * *normal) / sin_i;
+            let projected_o = (*outgoing - cos_o * *normal) / sin_o;
+            cos_phi = projected_i.dot(projected_o).max(0.0);
+        }
+        let (sin_alpha, tan_beta) = if cos_i.abs() > cos_o.abs() {
+            (sin_o, sin_i / cos_i.abs())
+        } else {
+            (sin_i, sin_o / cos_o.abs().max(1e-4))
+        };
+        a + b * cos_phi * sin_alpha * tan_beta
+    }
+}
+
+impl Material for OrenNayar {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        let incoming = -ray.direction.make_unit_vector();
+        let normal = if incoming.dot(hit_record.normal) < 0.0 { -hit_record.normal } else { hit_record.normal };
+        let outgoing = random_cosine_direction(&normal, sampler);
+        Some(Scatter {
+            color: self.factor(&normal, &incoming, &outgoing) * self.albedo,
+            ray: Some(Ray::new(hit_record.p_vect, outgoing)),
+            lobe: Some(Lobe::Diffuse),
+        })
+    }
+}
+
+/// Thin translucent sheet, such as paper or a lampshade: light is diffusely
+/// reflected on the side it arrives from, or diffusely transmitted to the
+/// other side, each chosen in proportion to its luminance.
+#[derive(Clone)]
+pub struct DiffuseTransmission {
+    pub reflectance: Color,
+    pub transmittance: Color,
+}
+
+impl DiffuseTransmission {
+    pub fn new(reflectance: Color, transmittance: Color) -> DiffuseTransmission {
+        DiffuseTransmission { reflectance, transmittance }
+    }
+}
+
+impl Material for DiffuseTransmission {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        let normal = if ray.direction.dot(hit_record.normal) < 0.0 { hit_record.normal } else { -hit_record.normal };
+        let reflect_weight = luminance(self.reflectance);
+        let total = reflect_weight + luminance(self.transmittance);
+        if total <= 0.0 {
+            return Some(Scatter { color: Color::black(), ray: None, lobe: None });
+        }
+        let reflect_probability = reflect_weight / total;
+        let (direction, color, lobe) = if sampler.get_1d() < reflect_probability {
+            (random_cosine_direction(&normal, sampler), self.reflectance / reflect_probability, Lobe::Diffuse)
+        } else {
+            let color = self.transmittance / (1.0 - reflect_probability);
+            (random_cosine_direction(&-normal, sampler), color, Lobe::Transmission)
+        };
+        Some(Scatter {
+            color,
+            ray: Some(Ray::new(hit_record.p_vect, direction)),
+            lobe: Some(lobe),
         })
     }
 }
@@ -66,25 +264,155 @@ fn reflect(a: &Vec3, b: &Vec3) -> Vec3 {
 pub struct Metal {
     pub albedo: Color,
     pub fuzz: f32,
+    /// Coating whose interferences replace `albedo` as the reflectance.
+    pub thin_film: Option<ThinFilm>,
 }
 
 impl Metal {
     pub fn new(a: Color, f: f32) -> Metal {
-        Metal { albedo: a, fuzz: if f > 1.0 { 1.0 } else {f} }
+        Metal { albedo: a, fuzz: if f > 1.0 { 1.0 } else {f}, thin_film: None }
     }
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<Scatter> {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
         let reflected = reflect(&ray.direction, &hit_record.normal);
-        let scattered = Ray::new(hit_record.p_vect, reflected + self.fuzz * random_in_unit_sphere());
-        Some(Scatter{
-            color: self.albedo,
-            ray: if scattered.direction.dot(hit_record.normal) > 0.0 { Some(scattered) } else { None },
-        })
+        let scattered = Ray::new(hit_record.p_vect, reflected + self.fuzz * random_in_unit_sphere(sampler));
+        let color = match self.thin_film {
+            Some(ref film) => {
+                let cosine = -ray.direction.make_unit_vector().dot(hit_record.normal);
+                film.reflectance(hit_record, ray.wavelength, cosine, 1.0, Substrate::Conductor(self.albedo))
+            }
+            None => self.albedo,
+        };
+        // Fuzz may send the ray under the surface, which absorbs it.
+        let ray = if scattered.direction.dot(hit_record.normal) > 0.0 { Some(scattered) } else { None };
+        Some(Scatter { color, lobe: ray.map(|_| Lobe::Specular), ray })
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.thin_film.is_some()
+    }
+}
+
+/// Wavelengths, in nanometres, standing for the red, green and blue
+/// channels when evaluating wavelength-dependent effects in RGB.
+const RGB_WAVELENGTHS: [f32; 3] = [650.0, 532.0, 450.0];
+
+/// What lies under a thin film.
+#[derive(Copy, Clone)]
+enum Substrate {
+    Dielectric(f32),
+    /// A metal of the given reflectance at normal incidence, approximated as
+    /// a real reflection amplitude with a phase shift of π.
+    Conductor(Color),
+}
+
+/// Thin transparent coating, such as a soap film, an oil slick or the
+/// anti-reflective layer of a lens, whose reflectance comes from the
+/// interference between the light reflected on both of its sides (Airy
+/// summation).
+#[derive(Clone)]
+pub struct ThinFilm {
+    /// Thickness of the film, in nanometres.
+    pub thickness: f32,
+    pub ior: f32,
+    /// Scales `thickness` over the surface, e.g. for the swirls of a bubble.
+    pub thickness_map: Option<TextureRef>,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: f32, ior: f32) -> ThinFilm {
+        ThinFilm { thickness, ior, thickness_map: None }
+    }
+
+    /// Reflectance for light arriving from a medium of index `outer_ior`
+    /// at `cosine` to the normal, per channel in RGB or at the ray
+    /// wavelength in spectral mode.
+    fn reflectance(
+        &self, hit_record: &HitRecord, wavelength: Option<f32>, cosine: f32, outer_ior: f32, substrate: Substrate
+    ) -> Color {
+        let thickness = self.thickness * self.thickness_map.as_ref()
+            .map_or(1.0, |map| map.value_f32(hit_record.u, hit_record.v, &hit_record.p_vect));
+        let evaluate = |lambda: f32, f0: f32| {
+            let base = match substrate {
+                Substrate::Dielectric(ior) => FilmBase::Ior(ior),
+                Substrate::Conductor(_) => FilmBase::Reflectance(f0),
+            };
+            airy_reflectance(cosine.clamp(0.0, 1.0), outer_ior, self.ior, thickness, lambda, base)
+        };
+        let albedo = match substrate {
+            Substrate::Conductor(albedo) => albedo,
+            Substrate::Dielectric(_) => Color::black(),
+        };
+        match wavelength {
+            Some(lambda) => {
+                let reflectance = evaluate(lambda, rgb_to_spectrum(albedo, lambda));
+                Color::new(reflectance, reflectance, reflectance)
+            }
+            None => Color::new(
+                evaluate(RGB_WAVELENGTHS[0], albedo.red),
+                evaluate(RGB_WAVELENGTHS[1], albedo.green),
+                evaluate(RGB_WAVELENGTHS[2], albedo.blue),
+            ),
+        }
     }
 }
 
+/// The far side of a thin film at a single wavelength.
+#[derive(Copy, Clone)]
+enum FilmBase {
+    Ior(f32),
+    Reflectance(f32),
+}
+
+/// Reflectance of a film of index `film_ior` and `thickness` nanometres
+/// at `lambda` nanometres, lit from a medium of index `outer_ior` at
+/// `cos_outer` to the normal, averaged over both polarisations.
+fn airy_reflectance(
+    cos_outer: f32, outer_ior: f32, film_ior: f32, thickness: f32, lambda: f32, base: FilmBase
+) -> f32 {
+    let sin2_outer = 1.0 - cos_outer * cos_outer;
+    let sin2_film = outer_ior * outer_ior * sin2_outer / (film_ior * film_ior);
+    if sin2_film >= 1.0 {
+        return 1.0;
+    }
+    let cos_film = (1.0 - sin2_film).sqrt();
+    let s_amplitude = |n_i: f32, cos_i: f32, n_t: f32, cos_t: f32| {
+        (n_i * cos_i - n_t * cos_t) / (n_i * cos_i + n_t * cos_t)
+    };
+    let p_amplitude = |n_i: f32, cos_i: f32, n_t: f32, cos_t: f32| {
+        (n_t * cos_i - n_i * cos_t) / (n_t * cos_i + n_i * cos_t)
+    };
+    let (base_s, base_p) = match base {
+        FilmBase::Ior(base_ior) => {
+            let sin2_base = film_ior * film_ior * sin2_film / (base_ior * base_ior);
+            if sin2_base >= 1.0 {
+                return 1.0;
+            }
+            let cos_base = (1.0 - sin2_base).sqrt();
+            (
+                s_amplitude(film_ior, cos_film, base_ior, cos_base),
+                p_amplitude(film_ior, cos_film, base_ior, cos_base),
+            )
+        }
+        FilmBase::Reflectance(f0) => {
+            // Denser than the film, with the sign conventions used above.
+            let amplitude = f0.clamp(0.0, 1.0).sqrt();
+            (-amplitude, amplitude)
+        }
+    };
+    let phase = 4.0 * std::f32::consts::PI * film_ior * thickness * cos_film / lambda;
+    let airy = |r12: f32, r23: f32| {
+        let cross = 2.0 * r12 * r23 * phase.cos();
+        (r12 * r12 + r23 * r23 + cross) / (1.0 + r12 * r12 * r23 * r23 + cross)
+    };
+    0.5 * (
+        airy(s_amplitude(outer_ior, cos_outer, film_ior, cos_film), base_s)
+            + airy(p_amplitude(outer_ior, cos_outer, film_ior, cos_film), base_p)
+    )
+}
+
 fn refract(v: &Vec3, n: &Vec3, ni_over_t: f32) -> Option<Vec3> {
     let unit_vector = (*v).make_unit_vector();
     let dt = unit_vector.dot(*n);
@@ -102,45 +430,1026 @@ fn schlick(cosine: f32, ref_idx: f32) -> f32{
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
 
+/// Samples a microfacet normal around `normal` from the GGX distribution
+/// of width `alpha`, proportionally to `D(m) |m.n|` (Walter et al. 2007).
+fn sample_ggx_normal(normal: &Vec3, alpha: f32, sampler: &mut dyn Sampler) -> Vec3 {
+    let (xi, u) = sampler.get_2d();
+    let phi = 2.0 * std::f32::consts::PI * u;
+    let tan2_theta = alpha * alpha * xi / (1.0 - xi);
+    let cos_theta = 1.0 / (1.0 + tan2_theta).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let (tangent, bitangent) = normal.orthonormal_basis();
+    (sin_theta * phi.cos() * tangent + sin_theta * phi.sin() * bitangent + cos_theta * *normal)
+        .make_unit_vector()
+}
+
+/// Smith masking term of the GGX distribution for direction `v`.
+fn ggx_g1(v: &Vec3, m: &Vec3, normal: &Vec3, alpha: f32) -> f32 {
+    let v_dot_n = v.dot(*normal);
+    if v.dot(*m) * v_dot_n <= 0.0 {
+        return 0.0;
+    }
+    let cos2 = v_dot_n * v_dot_n;
+    let tan2 = (1.0 - cos2) / cos2;
+    2.0 / (1.0 + (1.0 + alpha * alpha * tan2).sqrt())
+}
+
+/// Wavelength of the helium d line, in nanometres, at which dispersive
+/// indices are evaluated when tracing RGB.
+pub const D_LINE: f32 = 587.6;
+const F_LINE: f32 = 486.1;
+const C_LINE: f32 = 656.3;
+
+/// Index of refraction of a dielectric, as a function of wavelength.
+#[derive(Copy, Clone, Debug)]
+pub enum Ior {
+    Constant(f32),
+    /// Cauchy's equation `n = a + b / λ²`, λ in micrometres.
+    Cauchy { a: f32, b: f32 },
+    /// Sellmeier's equation `n² = 1 + Σ b λ² / (λ² - c)`, λ in micrometres.
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Ior {
+    /// Cauchy fit of a glass from its index at the d line and its Abbe
+    /// number (lower is more dispersive).
+    pub fn new_cauchy_from_abbe(n_d: f32, abbe: f32) -> Ior {
+        let inverse_square = |lambda: f32| 1.0e6 / (lambda * lambda);
+        let b = (n_d - 1.0) / (abbe * (inverse_square(F_LINE) - inverse_square(C_LINE)));
+        Ior::Cauchy { a: n_d - b * inverse_square(D_LINE), b }
+    }
+    /// Schott N-BK7 crown glass.
+    pub fn new_bk7() -> Ior {
+        Ior::Sellmeier {
+            b: [1.039_612, 0.231_792_3, 1.010_469_5],
+            c: [0.006_000_699, 0.020_017_914, 103.560_65],
+        }
+    }
+    /// Diamond (Peter 1923).
+    pub fn new_diamond() -> Ior {
+        Ior::Sellmeier { b: [0.3306, 4.3356, 0.0], c: [0.030_625, 0.011_236, 0.0] }
+    }
+
+    /// Index at `wavelength` nanometres, or at the d line for RGB rays.
+    pub fn at(&self, wavelength: Option<f32>) -> f32 {
+        let lambda = wavelength.unwrap_or(D_LINE) / 1000.0;
+        let lambda2 = lambda * lambda;
+        match *self {
+            Ior::Constant(n) => n,
+            Ior::Cauchy { a, b } => a + b / lambda2,
+            Ior::Sellmeier { b, c } => {
+                let mut n2 = 1.0;
+                for i in 0..3 {
+                    n2 += b[i] * lambda2 / (lambda2 - c[i]);
+                }
+                n2.sqrt()
+            }
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(*self, Ior::Constant(_))
+    }
+}
+
 #[derive(Clone)]
 pub struct Dielectric {
-    pub ref_idx: f32
+    pub ref_idx: Ior,
+    /// Perceptual roughness of the interface, `0.0` being perfectly smooth.
+    /// The GGX width is `roughness * roughness`.
+    pub roughness: f32,
+    /// Beer–Lambert absorption coefficient of the medium, per scene unit.
+    pub absorption: Color,
+    /// Coating whose interferences replace the Fresnel reflectance of a
+    /// smooth interface; rough interfaces ignore it.
+    pub thin_film: Option<ThinFilm>,
 }
 impl Dielectric {
     pub fn new(index: f32) -> Dielectric {
-        Dielectric { ref_idx: index }
+        Dielectric::new_dispersive(Ior::Constant(index))
     }
-}
-impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<Scatter> {
+    pub fn new_dispersive(ref_idx: Ior) -> Dielectric {
+        Dielectric { ref_idx, roughness: 0.0, absorption: Color::black(), thin_film: None }
+    }
+    pub fn new_rough(index: f32, roughness: f32, absorption: Color) -> Dielectric {
+        Dielectric {
+            ref_idx: Ior::Constant(index),
+            roughness: roughness.clamp(0.0, 1.0),
+            absorption,
+            thin_film: None,
+        }
+    }
+
+    /// Smooth interface under a thin film: reflection and refraction are
+    /// chosen with the average film reflectance and weighted per channel.
+    fn scatter_thin_film(
+        ray: &Ray, hit_record: &HitRecord, ref_idx: f32, film: &ThinFilm, attenuation: Color,
+        sampler: &mut dyn Sampler,
+    ) -> Scatter {
+        let entering = ray.direction.dot(hit_record.normal) < 0.0;
+        let (outward_normal, outer_ior, inner_ior) = if entering {
+            (hit_record.normal, 1.0, ref_idx)
+        } else {
+            (-hit_record.normal, ref_idx, 1.0)
+        };
+        let reflected = Scatter {
+            color: attenuation,
+            ray: Some(Ray::new(hit_record.p_vect, reflect(&ray.direction, &outward_normal))),
+            lobe: Some(Lobe::Specular),
+        };
+        let refracted = match refract(&ray.direction, &outward_normal, outer_ior / inner_ior) {
+            Some(refracted) => refracted,
+            None => return reflected,
+        };
+        let cosine = -ray.direction.make_unit_vector().dot(outward_normal);
+        let reflectance = film.reflectance(
+            hit_record, ray.wavelength, cosine, outer_ior, Substrate::Dielectric(inner_ior)
+        );
+        let probability = ((reflectance.red + reflectance.green + reflectance.blue) / 3.0).clamp(1e-3, 1.0 - 1e-3);
+        if sampler.get_1d() < probability {
+            Scatter { color: attenuation * reflectance / probability, ..reflected }
+        } else {
+            Scatter {
+                color: attenuation * (Color::white() - reflectance) / (1.0 - probability),
+                ray: Some(Ray::new(hit_record.p_vect, refracted)),
+                lobe: Some(Lobe::Transmission),
+            }
+        }
+    }
+
+    fn scatter_smooth(
+        ray: &Ray, hit_record: &HitRecord, ref_idx: f32, attenuation: Color, sampler: &mut dyn Sampler
+    ) -> Scatter {
         let outward_normal: Vec3;
         let ni_over_t: f32;
         let cosine: f32;
-        
+
         let reflected = reflect(&ray.direction, &hit_record.normal);
-        let attenuation = Color::white();
-        let mut rng = thread_rng();
 
         if ray.direction.dot(hit_record.normal) > 0.0 {
             outward_normal = - hit_record.normal;
-            ni_over_t = self.ref_idx;
-            cosine = self.ref_idx * ray.direction.dot(hit_record.normal) / ray.direction.length();
+            ni_over_t = ref_idx;
+            cosine = ref_idx * ray.direction.dot(hit_record.normal) / ray.direction.length();
         } else {
             outward_normal = hit_record.normal;
-            ni_over_t = 1.0 / self.ref_idx;
+            ni_over_t = 1.0 / ref_idx;
             cosine = - ray.direction.dot(hit_record.normal) / ray.direction.length();
         }
         if let Some(refracted) = refract(&ray.direction, &outward_normal, ni_over_t) {
-            if rng.gen_range(0.0, 1.0) > schlick(cosine, self.ref_idx){
-                return Some(Scatter { 
+            if sampler.get_1d() > schlick(cosine, ref_idx){
+                return Scatter {
                     color: attenuation,
-                    ray: Some(Ray::new(hit_record.p_vect, refracted))
-                });
+                    ray: Some(Ray::new(hit_record.p_vect, refracted)),
+                    lobe: Some(Lobe::Transmission),
+                };
             }
         }
-        Some(Scatter {
-                color: attenuation,
-                ray: Some(Ray::new(hit_record.p_vect, reflected))
-            })
+        Scatter {
+            color: attenuation,
+            ray: Some(Ray::new(hit_record.p_vect, reflected)),
+            lobe: Some(Lobe::Specular),
+        }
+    }
+
+    /// Microfacet reflection and transmission through a GGX rough interface
+    /// (Walter et al. 2007, "Microfacet Models for Refraction through Rough
+    /// Surfaces"). The microfacet normal is sampled from `D(m) |m.n|`, which
+    /// leaves `|i.m| G(i, o, m) / (|i.n| |m.n|)` as the sample weight.
+    fn scatter_rough(
+        ray: &Ray, hit_record: &HitRecord, ref_idx: f32, roughness: f32, attenuation: Color,
+        sampler: &mut dyn Sampler,
+    ) -> Scatter {
+        let alpha = roughness * roughness;
+        let incoming = -ray.direction.make_unit_vector();
+        let entering = incoming.dot(hit_record.normal) > 0.0;
+        let (normal, eta) = if entering {
+            (hit_record.normal, 1.0 / ref_idx)
+        } else {
+            (-hit_record.normal, ref_idx)
+        };
+        let absorbed = Scatter { color: attenuation, ray: None, lobe: None };
+
+        let micro_normal = sample_ggx_normal(&normal, alpha, sampler);
+        let i_dot_m = incoming.dot(micro_normal);
+        if i_dot_m <= 0.0 {
+            return absorbed;
+        }
+        let sin2_t = eta * eta * (1.0 - i_dot_m * i_dot_m);
+        let fresnel = if sin2_t >= 1.0 {
+            1.0
+        } else if entering {
+            schlick(i_dot_m, ref_idx)
+        } else {
+            schlick((1.0 - sin2_t).sqrt(), ref_idx)
+        };
+
+        let outgoing = if sampler.get_1d() < fresnel {
+            let reflected = 2.0 * i_dot_m * micro_normal - incoming;
+            if reflected.dot(normal) <= 0.0 {
+                return absorbed;
+            }
+            reflected
+        } else {
+            let cos_t = (1.0 - sin2_t).sqrt();
+            let refracted = (eta * i_dot_m - cos_t) * micro_normal - eta * incoming;
+            if refracted.dot(normal) >= 0.0 {
+                return absorbed;
+            }
+            refracted
+        };
+        let shadowing = ggx_g1(&incoming, &micro_normal, &normal, alpha)
+            * ggx_g1(&outgoing, &micro_normal, &normal, alpha);
+        let weight = i_dot_m * shadowing / (incoming.dot(normal) * micro_normal.dot(normal));
+        Scatter {
+            color: weight * attenuation,
+            ray: Some(Ray::new(hit_record.p_vect, outgoing)),
+            lobe: Some(if outgoing.dot(normal) < 0.0 { Lobe::Transmission } else { Lobe::Specular }),
+        }
+    }
+}
+impl Material for Dielectric {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        let mut attenuation = Color::white();
+        if ray.direction.dot(hit_record.normal) > 0.0 {
+            // The ray travelled inside the medium up to this hit.
+            let distance = hit_record.t_factor * ray.direction.length();
+            attenuation = (-distance * self.absorption).exp();
+        }
+        let ref_idx = self.ref_idx.at(ray.wavelength);
+        if self.roughness > 0.0 {
+            Some(Dielectric::scatter_rough(ray, hit_record, ref_idx, self.roughness, attenuation, sampler))
+        } else if let Some(ref film) = self.thin_film {
+            Some(Dielectric::scatter_thin_film(ray, hit_record, ref_idx, film, attenuation, sampler))
+        } else {
+            Some(Dielectric::scatter_smooth(ray, hit_record, ref_idx, attenuation, sampler))
+        }
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.ref_idx.is_dispersive() || (self.thin_film.is_some() && self.roughness == 0.0)
+    }
+}
+
+/// Translucent material such as wax, skin or marble: a smooth dielectric
+/// boundary around a scattering medium, through which the integrator
+/// random-walks light until it leaves the surface again.
+#[derive(Clone)]
+pub struct Subsurface {
+    pub ior: f32,
+    pub medium: Medium,
+}
+
+impl Subsurface {
+    pub fn new(albedo: Color, mean_free_path: f32, anisotropy: f32, ior: f32) -> Subsurface {
+        Subsurface { ior, medium: Medium::new(albedo, mean_free_path, anisotropy) }
+    }
+}
+
+impl Material for Subsurface {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        Some(Dielectric::scatter_smooth(ray, hit_record, self.ior, Color::white(), sampler))
+    }
+
+    fn interior(&self) -> Option<Medium> {
+        Some(self.medium)
+    }
+}
+
+/// Schlick's approximation of the Fresnel reflectance of a surface whose
+/// reflectance at normal incidence is `f0`.
+fn schlick_color(cosine: f32, f0: Color) -> Color {
+    f0 + (1.0 - cosine).max(0.0).powi(5) * (Color::white() - f0)
+}
+
+fn luminance(color: Color) -> f32 {
+    0.2126 * color.red + 0.7152 * color.green + 0.0722 * color.blue
+}
+
+/// Reflection off a GGX microfacet surface of width `alpha`. The weight
+/// returned is the BRDF times the cosine over the sampling density.
+fn scatter_ggx_reflection(
+    incoming: &Vec3, normal: &Vec3, alpha: f32, f0: Color, sampler: &mut dyn Sampler
+) -> Option<(Vec3, Color)> {
+    let micro_normal = sample_ggx_normal(normal, alpha, sampler);
+    let i_dot_m = incoming.dot(micro_normal);
+    if i_dot_m <= 0.0 {
+        return None;
+    }
+    let reflected = 2.0 * i_dot_m * micro_normal - *incoming;
+    if reflected.dot(*normal) <= 0.0 {
+        return None;
+    }
+    let shadowing = ggx_g1(incoming, &micro_normal, normal, alpha)
+        * ggx_g1(&reflected, &micro_normal, normal, alpha);
+    let weight = i_dot_m * shadowing / (incoming.dot(*normal) * micro_normal.dot(*normal));
+    Some((reflected, weight * schlick_color(i_dot_m, f0)))
+}
+
+/// Principled material after Burley, "Physically Based Shading at Disney"
+/// (2012), and the target of asset importers: the other materials convert
+/// into it through `From`.
+///
+/// Each lobe is sampled on its own, chosen with a probability proportional
+/// to its estimated contribution. The clearcoat uses a GGX distribution
+/// instead of Disney's GTR1.
+#[derive(Clone)]
+pub struct Principled {
+    pub base_color: TextureRef,
+    pub metallic: TextureRef,
+    /// Perceptual roughness, the GGX width being `roughness * roughness`.
+    pub roughness: TextureRef,
+    /// Dielectric specular reflectance, `0.5` giving the usual 4%.
+    pub specular: f32,
+    /// Retro-reflective grazing lobe, for cloth.
+    pub sheen: f32,
+    /// Blend of the sheen colour from white to the hue of the base colour.
+    pub sheen_tint: f32,
+    pub clearcoat: f32,
+    /// Glossiness of the clearcoat, `1.0` being a mirror finish.
+    pub clearcoat_gloss: f32,
+    /// Fraction of the dielectric base that is transmitted instead of
+    /// diffused.
+    pub transmission: f32,
+    pub ior: f32,
+    pub emission: TextureRef,
+    pub emission_strength: f32,
+}
+
+impl Principled {
+    pub fn new(base_color: Color) -> Principled {
+        Principled::new_textured(ConstantTexture::new_ref(base_color))
+    }
+    pub fn new_textured(base_color: TextureRef) -> Principled {
+        Principled {
+            base_color,
+            metallic: ConstantTexture::new_f32_ref(0.0),
+            roughness: ConstantTexture::new_f32_ref(0.5),
+            specular: 0.5,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+            ior: 1.45,
+            emission: ConstantTexture::new_ref(Color::black()),
+            emission_strength: 1.0,
+        }
+    }
+
+    fn sheen_color(&self, base_color: Color) -> Color {
+        let luminance = luminance(base_color);
+        let tint = if luminance > 0.0 { base_color / luminance } else { Color::white() };
+        self.sheen * ((1.0 - self.sheen_tint) * Color::white() + self.sheen_tint * tint)
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        let (u, v, p_vect) = (hit_record.u, hit_record.v, &hit_record.p_vect);
+        let base_color = self.base_color.value(u, v, p_vect);
+        let metallic = self.metallic.value_f32(u, v, p_vect).clamp(0.0, 1.0);
+        let roughness = self.roughness.value_f32(u, v, p_vect).clamp(0.0, 1.0);
+        let transmission = (1.0 - metallic) * self.transmission.clamp(0.0, 1.0);
+
+        let incoming = -ray.direction.make_unit_vector();
+        let mut normal = hit_record.normal;
+        if incoming.dot(normal) <= 0.0 {
+            if transmission > 0.0 {
+                // Leaving the transmissive medium.
+                return Some(self.scatter_transmission(ray, hit_record, roughness, base_color, sampler));
+            }
+            normal = -normal;
+        }
+        let cosine = incoming.dot(normal);
+
+        let diffuse = (1.0 - metallic) * (1.0 - transmission);
+        let f0 = (1.0 - metallic) * (0.08 * self.specular) * Color::white() + metallic * base_color;
+        let specular = 1.0 - transmission;
+        let clearcoat = 0.25 * self.clearcoat;
+        let weights = [
+            diffuse * luminance(base_color + self.sheen_color(base_color)),
+            specular * luminance(schlick_color(cosine, f0)),
+            clearcoat * schlick(cosine, 1.5),
+            transmission,
+        ];
+        let total: f32 = weights.iter().sum();
+        if total <= 0.0 {
+            return Some(Scatter { color: Color::black(), ray: None, lobe: None });
+        }
+
+        let mut choice = sampler.get_1d() * total;
+        let mut lobe = 0;
+        while lobe < weights.len() - 1 && choice >= weights[lobe] {
+            choice -= weights[lobe];
+            lobe += 1;
+        }
+        let probability = weights[lobe] / total;
+
+        let scattered = match lobe {
+            0 => {
+                let target = random_cosine_direction(&normal, sampler);
+                let half = (incoming + target).make_unit_vector();
+                let sheen = (1.0 - half.dot(incoming)).max(0.0).powi(5)
+                    * std::f32::consts::PI * self.sheen_color(base_color);
+                Some((target, diffuse * (base_color + sheen), Lobe::Diffuse))
+            }
+            1 => scatter_ggx_reflection(&incoming, &normal, (roughness * roughness).max(1e-3), f0, sampler)
+                .map(|(direction, color)| (direction, specular * color, Lobe::Specular)),
+            2 => {
+                let alpha = 0.1 + (0.001 - 0.1) * self.clearcoat_gloss;
+                scatter_ggx_reflection(&incoming, &normal, alpha, 0.04 * Color::white(), sampler)
+                    .map(|(direction, color)| (direction, clearcoat * color, Lobe::Specular))
+            }
+            _ => {
+                let scatter = self.scatter_transmission(ray, hit_record, roughness, base_color, sampler);
+                scatter.ray.zip(scatter.lobe).map(|(ray, lobe)| (ray.direction, transmission * scatter.color, lobe))
+            }
+        };
+        Some(match scattered {
+            Some((direction, color, lobe)) => Scatter {
+                color: color / probability,
+                ray: Some(Ray::new(hit_record.p_vect, direction)),
+                lobe: Some(lobe),
+            },
+            None => Scatter { color: Color::black(), ray: None, lobe: None },
+        })
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Color {
+        self.emission_strength * self.emission.value(hit_record.u, hit_record.v, &hit_record.p_vect)
+    }
+}
+
+impl Principled {
+    /// Refraction through the surface, tinted by the square root of the base
+    /// colour so that entering and leaving tints by the base colour.
+    fn scatter_transmission(
+        &self, ray: &Ray, hit_record: &HitRecord, roughness: f32, base_color: Color, sampler: &mut dyn Sampler
+    ) -> Scatter {
+        let tint = base_color.sqrt();
+        if roughness > 0.0 {
+            Dielectric::scatter_rough(ray, hit_record, self.ior, roughness, tint, sampler)
+        } else {
+            Dielectric::scatter_smooth(ray, hit_record, self.ior, tint, sampler)
+        }
+    }
+}
+
+impl From<Lambertian> for Principled {
+    fn from(lambertian: Lambertian) -> Principled {
+        let mut principled = Principled::new(lambertian.albedo);
+        principled.roughness = ConstantTexture::new_f32_ref(1.0);
+        principled.specular = 0.0;
+        principled
+    }
+}
+
+impl From<Metal> for Principled {
+    fn from(metal: Metal) -> Principled {
+        let mut principled = Principled::new(metal.albedo);
+        principled.metallic = ConstantTexture::new_f32_ref(1.0);
+        principled.roughness = ConstantTexture::new_f32_ref(metal.fuzz.sqrt());
+        principled
+    }
+}
+
+impl From<Dielectric> for Principled {
+    fn from(dielectric: Dielectric) -> Principled {
+        let mut principled = Principled::new(Color::white());
+        principled.roughness = ConstantTexture::new_f32_ref(dielectric.roughness);
+        principled.transmission = 1.0;
+        principled.ior = dielectric.ref_idx.at(None);
+        principled
+    }
+}
+
+/// Bends the normal of the hit point towards `normal`, keeping it on the
+/// side of the geometric normal, before scattering with the wrapped material.
+fn scatter_with_normal<M: Material>(
+    material: &M, ray: &Ray, hit_record: &HitRecord, normal: Vec3, sampler: &mut dyn Sampler
+) -> Option<Scatter> {
+    let mut normal = normal.make_unit_vector();
+    if normal.dot(hit_record.normal) <= 0.0 {
+        normal = hit_record.normal;
+    }
+    let tangent = (hit_record.tangent - hit_record.tangent.dot(normal) * normal).make_unit_vector();
+    material.scatter(ray, &HitRecord { normal, tangent, material, ..*hit_record }, sampler)
+}
+
+/// Perturbs the shading normal of `material` with a tangent-space normal
+/// map, whose red, green and blue channels encode the tangent, bitangent and
+/// normal components remapped from `[-1, 1]` to `[0, 1]`.
+#[derive(Clone)]
+pub struct NormalMapped<M: Material> {
+    pub material: M,
+    pub normal_map: TextureRef,
+    /// Blend from the geometric normal (`0.0`) to the mapped one (`1.0`).
+    pub strength: f32,
+}
+
+impl<M: Material> NormalMapped<M> {
+    pub fn new(material: M, normal_map: TextureRef, strength: f32) -> NormalMapped<M> {
+        NormalMapped { material, normal_map, strength }
+    }
+}
+
+impl<M: Material> Material for NormalMapped<M> {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        let encoded = self.normal_map.value(hit_record.u, hit_record.v, &hit_record.p_vect);
+        let local = Vec3::new(
+            self.strength * (2.0 * encoded.red - 1.0),
+            self.strength * (2.0 * encoded.green - 1.0),
+            1.0 + self.strength * (2.0 * encoded.blue - 2.0),
+        );
+        let normal = hit_record.normal;
+        let bitangent = normal.cross(hit_record.tangent);
+        let mapped = local.x * hit_record.tangent + local.y * bitangent + local.z * normal;
+        scatter_with_normal(&self.material, ray, hit_record, mapped, sampler)
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Color {
+        self.material.emitted(hit_record)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.material.is_dispersive()
+    }
+
+    fn interior(&self) -> Option<Medium> {
+        self.material.interior()
+    }
+
+    fn opacity(&self, hit_record: &HitRecord) -> f32 {
+        self.material.opacity(hit_record)
+    }
+}
+
+/// Perturbs the shading normal of `material` along the gradient of a height
+/// field read from any texture, by finite differences in texture space.
+#[derive(Clone)]
+pub struct BumpMapped<M: Material> {
+    pub material: M,
+    pub height: TextureRef,
+    /// Height of a unit texture value, in texture coordinate units.
+    pub scale: f32,
+    /// Step of the finite differences, in texture coordinate units.
+    pub delta: f32,
+}
+
+impl<M: Material> BumpMapped<M> {
+    pub fn new(material: M, height: TextureRef, scale: f32) -> BumpMapped<M> {
+        BumpMapped { material, height, scale, delta: 1.0 / 1024.0 }
+    }
+}
+
+impl<M: Material> Material for BumpMapped<M> {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        let (u, v, p_vect) = (hit_record.u, hit_record.v, &hit_record.p_vect);
+        let height = self.height.value_f32(u, v, p_vect);
+        let d_u = (self.height.value_f32(u + self.delta, v, p_vect) - height) / self.delta;
+        let d_v = (self.height.value_f32(u, v + self.delta, p_vect) - height) / self.delta;
+        let normal = hit_record.normal;
+        let bitangent = normal.cross(hit_record.tangent);
+        let bumped = normal - self.scale * (d_u * hit_record.tangent + d_v * bitangent);
+        scatter_with_normal(&self.material, ray, hit_record, bumped, sampler)
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Color {
+        self.material.emitted(hit_record)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.material.is_dispersive()
+    }
+
+    fn interior(&self) -> Option<Medium> {
+        self.material.interior()
+    }
+
+    fn opacity(&self, hit_record: &HitRecord) -> f32 {
+        self.material.opacity(hit_record)
+    }
+}
+
+/// Blend of two materials: each scatter picks `second` with the probability
+/// read from `mask`, and `first` otherwise. A constant texture gives a
+/// uniform blend.
+#[derive(Clone)]
+pub struct Mix<A: Material, B: Material> {
+    pub first: A,
+    pub second: B,
+    pub mask: TextureRef,
+}
+
+impl<A: Material, B: Material> Mix<A, B> {
+    pub fn new(first: A, second: B, mask: TextureRef) -> Mix<A, B> {
+        Mix { first, second, mask }
+    }
+
+    fn amount(&self, hit_record: &HitRecord) -> f32 {
+        self.mask.value_f32(hit_record.u, hit_record.v, &hit_record.p_vect).clamp(0.0, 1.0)
+    }
+}
+
+impl<A: Material, B: Material> Material for Mix<A, B> {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        if sampler.get_1d() < self.amount(hit_record) {
+            self.second.scatter(ray, &HitRecord { material: &self.second, ..*hit_record }, sampler)
+        } else {
+            self.first.scatter(ray, &HitRecord { material: &self.first, ..*hit_record }, sampler)
+        }
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Color {
+        let amount = self.amount(hit_record);
+        (1.0 - amount) * self.first.emitted(&HitRecord { material: &self.first, ..*hit_record })
+            + amount * self.second.emitted(&HitRecord { material: &self.second, ..*hit_record })
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.first.is_dispersive() || self.second.is_dispersive()
+    }
+
+    fn interior(&self) -> Option<Medium> {
+        self.first.interior().or_else(|| self.second.interior())
+    }
+
+    fn opacity(&self, hit_record: &HitRecord) -> f32 {
+        let amount = self.amount(hit_record);
+        (1.0 - amount) * self.first.opacity(&HitRecord { material: &self.first, ..*hit_record })
+            + amount * self.second.opacity(&HitRecord { material: &self.second, ..*hit_record })
+    }
+}
+
+/// Uses `front` on the side the normal points to and `back` on the other,
+/// for thin surfaces such as leaves or paper. The back material sees the
+/// normal flipped towards the ray.
+#[derive(Clone)]
+pub struct TwoSided<F: Material, B: Material> {
+    pub front: F,
+    pub back: B,
+}
+
+impl<F: Material, B: Material> TwoSided<F, B> {
+    pub fn new(front: F, back: B) -> TwoSided<F, B> {
+        TwoSided { front, back }
+    }
+}
+
+impl<F: Material, B: Material> Material for TwoSided<F, B> {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        if hit_record.front_face {
+            self.front.scatter(ray, &HitRecord { material: &self.front, ..*hit_record }, sampler)
+        } else {
+            let flipped = HitRecord {
+                normal: -hit_record.normal,
+                front_face: true,
+                material: &self.back,
+                ..*hit_record
+            };
+            self.back.scatter(ray, &flipped, sampler)
+        }
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Color {
+        if hit_record.front_face {
+            self.front.emitted(&HitRecord { material: &self.front, ..*hit_record })
+        } else {
+            let flipped = HitRecord {
+                normal: -hit_record.normal,
+                front_face: true,
+                material: &self.back,
+                ..*hit_record
+            };
+            self.back.emitted(&flipped)
+        }
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.front.is_dispersive() || self.back.is_dispersive()
+    }
+
+    fn interior(&self) -> Option<Medium> {
+        self.front.interior()
+    }
+
+    fn opacity(&self, hit_record: &HitRecord) -> f32 {
+        if hit_record.front_face {
+            self.front.opacity(&HitRecord { material: &self.front, ..*hit_record })
+        } else {
+            self.back.opacity(&HitRecord { material: &self.back, ..*hit_record })
+        }
+    }
+}
+
+/// A dielectric clearcoat over any base material. The coat reflects with
+/// Schlick's Fresnel probability; what it transmits scatters off the base
+/// and is tinted once by the coat. Inter-reflections between the coat and
+/// the base are ignored.
+#[derive(Clone)]
+pub struct Coated<M: Material> {
+    pub base: M,
+    pub ior: f32,
+    /// Perceptual roughness of the coat, the GGX width being its square.
+    pub roughness: f32,
+    pub tint: Color,
+}
+
+impl<M: Material> Coated<M> {
+    pub fn new(base: M, ior: f32, roughness: f32, tint: Color) -> Coated<M> {
+        Coated { base, ior, roughness: roughness.clamp(0.0, 1.0), tint }
+    }
+}
+
+impl<M: Material> Material for Coated<M> {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        let incoming = -ray.direction.make_unit_vector();
+        let normal = if hit_record.front_face { hit_record.normal } else { -hit_record.normal };
+        let fresnel = schlick(incoming.dot(normal).max(0.0), self.ior);
+        if sampler.get_1d() < fresnel {
+            let reflected = if self.roughness > 0.0 {
+                let alpha = self.roughness * self.roughness;
+                scatter_ggx_reflection(&incoming, &normal, alpha, Color::white(), sampler)
+            } else {
+                Some((reflect(&ray.direction, &normal), Color::white()))
+            };
+            return Some(match reflected {
+                Some((direction, color)) => Scatter {
+                    color,
+                    ray: Some(Ray::new(hit_record.p_vect, direction)),
+                    lobe: Some(Lobe::Specular),
+                },
+                None => Scatter { color: Color::black(), ray: None, lobe: None },
+            });
+        }
+        self.base
+            .scatter(ray, &HitRecord { material: &self.base, ..*hit_record }, sampler)
+            .map(|scatter| Scatter { color: self.tint * scatter.color, ..scatter })
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Color {
+        self.tint * self.base.emitted(&HitRecord { material: &self.base, ..*hit_record })
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+
+    fn interior(&self) -> Option<Medium> {
+        self.base.interior()
+    }
+
+    fn opacity(&self, hit_record: &HitRecord) -> f32 {
+        self.base.opacity(&HitRecord { material: &self.base, ..*hit_record })
+    }
+}
+
+/// Cuts `material` out where `mask` is transparent, for foliage or fences
+/// modelled as textured quads. Values between `0.0` and `1.0` let rays
+/// through stochastically, giving partial opacity on average.
+#[derive(Clone)]
+pub struct AlphaMasked<M: Material> {
+    pub material: M,
+    pub mask: TextureRef,
+}
+
+impl<M: Material> AlphaMasked<M> {
+    pub fn new(material: M, mask: TextureRef) -> AlphaMasked<M> {
+        AlphaMasked { material, mask }
+    }
+}
+
+impl<M: Material> Material for AlphaMasked<M> {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        self.material.scatter(ray, &HitRecord { material: &self.material, ..*hit_record }, sampler)
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Color {
+        self.material.emitted(&HitRecord { material: &self.material, ..*hit_record })
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.material.is_dispersive()
+    }
+
+    fn interior(&self) -> Option<Medium> {
+        self.material.interior()
+    }
+
+    fn opacity(&self, hit_record: &HitRecord) -> f32 {
+        let mask = self.mask.value_f32(hit_record.u, hit_record.v, &hit_record.p_vect).clamp(0.0, 1.0);
+        mask * self.material.opacity(&HitRecord { material: &self.material, ..*hit_record })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::texture::ImageTexture;
+    use crate::sampler::IndependentSampler;
+
+    /// Hit of `material` at the origin, facing up the z axis, in the middle
+    /// of its texture.
+    fn hit_record(material: &dyn Material, front_face: bool) -> HitRecord<'_> {
+        HitRecord {
+            t_factor: 1.0,
+            p_vect: Vec3::new_zero(),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            tangent: Vec3::new(1.0, 0.0, 0.0),
+            u: 0.5,
+            v: 0.5,
+            front_face,
+            material,
+        }
+    }
+
+    #[test]
+    fn test_ggx_normal_in_hemisphere() {
+        let mut sampler = IndependentSampler::new(0);
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        for _ in 0..100 {
+            let m = sample_ggx_normal(&normal, 0.5, &mut sampler);
+            assert!((m.length() - 1.0).abs() < 1e-4);
+            assert!(m.dot(normal) > 0.0);
+        }
+    }
+    #[test]
+    fn test_ggx_g1() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        assert!((ggx_g1(&normal, &normal, &normal, 0.5) - 1.0).abs() < 1e-6);
+        let grazing = Vec3::new(1.0, 0.0, 0.05).make_unit_vector();
+        let g = ggx_g1(&grazing, &normal, &normal, 0.5);
+        assert!(g > 0.0 && g < 1.0);
+        let back_facing = Vec3::new(-1.0, 0.0, 0.05).make_unit_vector();
+        assert_eq!(ggx_g1(&back_facing, &grazing, &normal, 0.5), 0.0);
+    }
+    #[test]
+    fn test_dielectric_absorption() {
+        let mut sampler = IndependentSampler::new(0);
+        let glass = Dielectric::new_rough(1.5, 0.0, Color::new(1.0, 0.0, 2.0));
+        let ray = Ray::new(Vec3::new_zero(), Vec3::new(0.0, 0.0, 2.0));
+        let hit_record = HitRecord { t_factor: 0.5, p_vect: ray.point_at_t(0.5), ..hit_record(&glass, false) };
+        let scatter = glass.scatter(&ray, &hit_record, &mut sampler).unwrap();
+        assert!((scatter.color.red - (-1.0_f32).exp()).abs() < 1e-6);
+        assert_eq!(scatter.color.green, 1.0);
+        assert!((scatter.color.blue - (-2.0_f32).exp()).abs() < 1e-6);
+    }
+    #[test]
+    fn test_ior_dispersion() {
+        assert_eq!(Ior::Constant(1.5).at(Some(400.0)), 1.5);
+        let flint = Ior::new_cauchy_from_abbe(1.6, 36.0);
+        assert!((flint.at(None) - 1.6).abs() < 1e-5);
+        let abbe = (flint.at(None) - 1.0) / (flint.at(Some(F_LINE)) - flint.at(Some(C_LINE)));
+        assert!((abbe - 36.0).abs() < 0.1);
+        let bk7 = Ior::new_bk7();
+        assert!((bk7.at(None) - 1.5168).abs() < 1e-3);
+        assert!(bk7.at(Some(400.0)) > bk7.at(Some(700.0)));
+        assert!((Ior::new_diamond().at(None) - 2.417).abs() < 5e-3);
+    }
+    #[test]
+    fn test_schlick_color() {
+        let f0 = Color::new(0.04, 0.5, 1.0);
+        let normal_incidence = schlick_color(1.0, f0);
+        assert!((normal_incidence.red - 0.04).abs() < 1e-6);
+        assert!((normal_incidence.green - 0.5).abs() < 1e-6);
+        let grazing = schlick_color(0.0, f0);
+        assert!((grazing.red - 1.0).abs() < 1e-6);
+        assert!((grazing.blue - 1.0).abs() < 1e-6);
+    }
+    #[test]
+    fn test_principled_emission() {
+        let mut sampler = IndependentSampler::new(0);
+        let mut lamp = Principled::new(Color::white());
+        lamp.emission = ConstantTexture::new_ref(Color::new(1.0, 0.5, 0.25));
+        lamp.emission_strength = 4.0;
+        let hit_record = hit_record(&lamp, true);
+        let emitted = lamp.emitted(&hit_record);
+        assert_eq!(emitted.red, 4.0);
+        assert_eq!(emitted.blue, 1.0);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let scatter = lamp.scatter(&ray, &hit_record, &mut sampler).unwrap();
+        if let Some(scattered) = scatter.ray {
+            assert!(scattered.direction.dot(hit_record.normal) > 0.0);
+        }
+    }
+
+    fn mirror_direction<M: Material>(material: &M, hit_record: &HitRecord) -> Vec3 {
+        let mut sampler = IndependentSampler::new(0);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        material.scatter(&ray, hit_record, &mut sampler).unwrap().ray.unwrap().direction
+    }
+    #[test]
+    fn test_normal_and_bump_mapping() {
+        let mirror = Metal::new(Color::white(), 0.0);
+        let hit_record = hit_record(&mirror, true);
+        let flat = NormalMapped::new(mirror.clone(), ConstantTexture::new_ref(Color::new(0.5, 0.5, 1.0)), 1.0);
+        let direction = mirror_direction(&flat, &hit_record);
+        assert!(direction.x.abs() < 1e-6 && direction.y.abs() < 1e-6);
+        let tilted = NormalMapped::new(mirror.clone(), ConstantTexture::new_ref(Color::new(0.75, 0.5, 1.0)), 1.0);
+        assert!(mirror_direction(&tilted, &hit_record).x > 0.1);
+
+        let level = BumpMapped::new(mirror.clone(), ConstantTexture::new_f32_ref(0.3), 1.0);
+        let direction = mirror_direction(&level, &hit_record);
+        assert!(direction.x.abs() < 1e-6 && direction.y.abs() < 1e-6);
+        let ramp = ImageTexture::new(2, 1, vec![Color::black(), Color::white()]);
+        let slope = BumpMapped::new(mirror.clone(), Arc::new(ramp), 0.1);
+        assert!(mirror_direction(&slope, &HitRecord { u: 0.5, ..hit_record }).x < -0.1);
+    }
+    #[test]
+    fn test_composite_materials() {
+        let mut sampler = IndependentSampler::new(0);
+        let red = Metal::new(Color::new(1.0, 0.0, 0.0), 0.0);
+        let blue = Metal::new(Color::new(0.0, 0.0, 1.0), 0.0);
+        let hit_record = hit_record(&red, true);
+        let down = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let up = Ray::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0));
+
+        let only_first = Mix::new(red.clone(), blue.clone(), ConstantTexture::new_f32_ref(0.0));
+        assert_eq!(only_first.scatter(&down, &hit_record, &mut sampler).unwrap().color.red, 1.0);
+        let only_second = Mix::new(red.clone(), blue.clone(), ConstantTexture::new_f32_ref(1.0));
+        assert_eq!(only_second.scatter(&down, &hit_record, &mut sampler).unwrap().color.blue, 1.0);
+
+        let two_sided = TwoSided::new(red.clone(), blue.clone());
+        assert_eq!(two_sided.scatter(&down, &hit_record, &mut sampler).unwrap().color.red, 1.0);
+        let back = HitRecord { front_face: false, ..hit_record };
+        let scatter = two_sided.scatter(&up, &back, &mut sampler).unwrap();
+        assert_eq!(scatter.color.blue, 1.0);
+        assert!(scatter.ray.unwrap().direction.z < 0.0);
+
+        let uncoated = Coated::new(blue, 1.0, 0.0, Color::new(0.5, 0.5, 0.5));
+        assert_eq!(uncoated.scatter(&down, &hit_record, &mut sampler).unwrap().color.blue, 0.5);
+    }
+    #[test]
+    fn test_metal_absorption() {
+        let mut sampler = IndependentSampler::new(0);
+        let fuzzy = Metal::new(Color::white(), 1.0);
+        let grazing = Ray::new(Vec3::new(-1.0, 0.0, 0.01), Vec3::new(1.0, 0.0, -0.01));
+        let mut absorbed = 0;
+        for _ in 0..100 {
+            let scatter = fuzzy.scatter(&grazing, &hit_record(&fuzzy, true), &mut sampler).unwrap();
+            // Light absorbed by the surface leaves by no lobe.
+            assert_eq!(scatter.ray.is_some(), scatter.lobe.is_some());
+            absorbed += usize::from(scatter.ray.is_none());
+        }
+        assert!(absorbed > 0);
+    }
+    #[test]
+    fn test_airy_reflectance() {
+        // Without a film, the Fresnel reflectance of the base remains.
+        let bare = airy_reflectance(1.0, 1.0, 1.5, 0.0, 550.0, FilmBase::Ior(1.5));
+        assert!((bare - 0.04).abs() < 1e-4);
+        // A quarter-wave layer of index sqrt(1.5) cancels the reflection.
+        let film_ior = 1.5_f32.sqrt();
+        let quarter_wave = 550.0 / (4.0 * film_ior);
+        let coated = airy_reflectance(1.0, 1.0, film_ior, quarter_wave, 550.0, FilmBase::Ior(1.5));
+        assert!(coated < 1e-4);
+        // A half-wave layer is invisible.
+        let half_wave = airy_reflectance(1.0, 1.0, 1.38, 550.0 / (2.0 * 1.38), 550.0, FilmBase::Ior(1.5));
+        assert!((half_wave - 0.04).abs() < 1e-3);
+        // The film colours the reflection of a grey metal.
+        let red = airy_reflectance(1.0, 1.0, 1.5, 300.0, 650.0, FilmBase::Reflectance(0.5));
+        let blue = airy_reflectance(1.0, 1.0, 1.5, 300.0, 450.0, FilmBase::Reflectance(0.5));
+        assert!((red - blue).abs() > 0.1);
+    }
+    #[test]
+    fn test_random_cosine_direction() {
+        let mut sampler = IndependentSampler::new(0);
+        let normal = Vec3::new(1.0, 2.0, -2.0).make_unit_vector();
+        let n = 20000;
+        let mut mean_cosine = 0.0;
+        for _ in 0..n {
+            let direction = random_cosine_direction(&normal, &mut sampler);
+            assert!((direction.length() - 1.0).abs() < 1e-4);
+            assert!(direction.dot(normal) >= 0.0);
+            mean_cosine += direction.dot(normal) / n as f32;
+        }
+        // E[cos] = 2/3 under a cosine-weighted density.
+        assert!((mean_cosine - 2.0 / 3.0).abs() < 0.01);
+    }
+    #[test]
+    fn test_oren_nayar_factor() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let incoming = Vec3::new(1.0, 0.0, 1.0).make_unit_vector();
+        let outgoing = Vec3::new(1.0, 0.0, 0.5).make_unit_vector();
+        let lambertian = OrenNayar::new(Color::white(), 0.0);
+        assert!((lambertian.factor(&normal, &incoming, &outgoing) - 1.0).abs() < 1e-6);
+        let rough = OrenNayar::new(Color::white(), 0.5);
+        let backward = rough.factor(&normal, &incoming, &outgoing);
+        let forward = rough.factor(&normal, &incoming, &Vec3::new(-1.0, 0.0, 0.5).make_unit_vector());
+        assert!(backward > forward);
+        assert!(forward < 1.0);
+    }
+    #[test]
+    fn test_diffuse_transmission() {
+        let mut sampler = IndependentSampler::new(0);
+        let paper = DiffuseTransmission::new(Color::black(), Color::new(0.5, 0.5, 0.5));
+        let hit_record = hit_record(&paper, true);
+        let down = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        for _ in 0..100 {
+            let scatter = paper.scatter(&down, &hit_record, &mut sampler).unwrap();
+            assert!(scatter.ray.unwrap().direction.z <= 0.0);
+            assert_eq!(scatter.color.green, 0.5);
+        }
     }
 }
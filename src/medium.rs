@@ -0,0 +1,86 @@
+use std::f32::consts::PI;
+
+use crate::vec3::Vec3;
+use crate::color::Color;
+use crate::sampler::Sampler;
+
+/// Homogeneous scattering medium filling the inside of a closed surface,
+/// traversed by the integrator with an analog random walk.
+#[derive(Copy, Clone, Debug)]
+pub struct Medium {
+    /// Fraction of the light surviving each scattering event.
+    pub albedo: Color,
+    /// Average distance between two scattering events, in scene units.
+    pub mean_free_path: f32,
+    /// Henyey–Greenstein asymmetry, from `-1.0` (backward) through `0.0`
+    /// (isotropic) to `1.0` (forward).
+    pub anisotropy: f32,
+}
+
+impl Medium {
+    pub fn new(albedo: Color, mean_free_path: f32, anisotropy: f32) -> Medium {
+        Medium {
+            albedo,
+            mean_free_path: mean_free_path.max(1e-6),
+            anisotropy: anisotropy.clamp(-0.99, 0.99),
+        }
+    }
+
+    /// Distance to the next scattering event along a ray, exponentially
+    /// distributed.
+    pub fn sample_distance(&self, sampler: &mut dyn Sampler) -> f32 {
+        let u = sampler.get_1d();
+        -(1.0 - u).ln() * self.mean_free_path
+    }
+
+    /// New direction of a ray travelling along the unit vector `direction`
+    /// after a scattering event, from the Henyey–Greenstein phase function.
+    /// It is sampled exactly, so the walk weight is only the albedo.
+    pub fn sample_direction(&self, direction: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (u, v) = sampler.get_2d();
+        let phi = 2.0 * PI * v;
+        let g = self.anisotropy;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let term = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            (1.0 + g * g - term * term) / (2.0 * g)
+        }
+        .clamp(-1.0, 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let (tangent, bitangent) = direction.orthonormal_basis();
+        sin_theta * phi.cos() * tangent + sin_theta * phi.sin() * bitangent + cos_theta * *direction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::IndependentSampler;
+
+    #[test]
+    fn test_sample_distance() {
+        let medium = Medium::new(Color::white(), 0.5, 0.0);
+        let mut sampler = IndependentSampler::new(0);
+        let n = 20000;
+        let mean: f32 = (0..n).map(|_| medium.sample_distance(&mut sampler)).sum::<f32>() / n as f32;
+        assert!((mean - 0.5).abs() < 0.02);
+    }
+    #[test]
+    fn test_henyey_greenstein_mean_cosine() {
+        let direction = Vec3::new(0.0, 0.6, 0.8);
+        let mut sampler = IndependentSampler::new(0);
+        for g in &[-0.5_f32, 0.0, 0.8] {
+            let medium = Medium::new(Color::white(), 1.0, *g);
+            let n = 20000;
+            let mut mean_cosine = 0.0;
+            for _ in 0..n {
+                let scattered = medium.sample_direction(&direction, &mut sampler);
+                assert!((scattered.length() - 1.0).abs() < 1e-4);
+                mean_cosine += scattered.dot(direction) / n as f32;
+            }
+            // The mean cosine of the Henyey–Greenstein phase function is g.
+            assert!((mean_cosine - g).abs() < 0.02);
+        }
+    }
+}
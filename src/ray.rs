@@ -4,11 +4,13 @@ use crate::vec3::Vec3;
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    /// Wavelength in nanometres when tracing spectrally, `None` for RGB.
+    pub wavelength: Option<f32>,
 }
 
 impl Ray {
     pub fn new (origin: Vec3, direction: Vec3) -> Ray {
-        Ray { origin, direction }
+        Ray { origin, direction, wavelength: None }
     }
 
     pub fn point_at_t(&self, t: f32) -> Vec3 {
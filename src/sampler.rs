@@ -0,0 +1,407 @@
+use rand::prelude::*;
+use rand::rngs::SmallRng;
+
+use crate::settings::SamplerKind;
+
+/// Source of the sample values of a render. Every value drawn for a pixel
+/// sample is one dimension of a point, so samplers that distribute points
+/// well over several dimensions reduce noise at equal sample counts. The
+/// camera, the integrator and the materials draw their dimensions in the
+/// same order for every sample of a pixel.
+pub trait Sampler {
+    /// Starts sample `index` of the pixel `(x, y)`, from the first dimension.
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32);
+    /// Next dimension, in `[0, 1)`.
+    fn get_1d(&mut self) -> f32;
+    /// Next two dimensions, stratified together where the sampler can.
+    fn get_2d(&mut self) -> (f32, f32);
+}
+
+/// Largest `f32` below one.
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+/// Finaliser of MurmurHash3, scrambling all the bits of `v`.
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+/// Hash of a sequence of values.
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |state, value| mix_bits(state ^ mix_bits(*value)))
+}
+
+/// Uniform value in `[0, 1)` from the hash of a sequence of values.
+pub fn hash_float(values: &[u64]) -> f32 {
+    bits_to_unit(hash(values) as u32)
+}
+
+fn bits_to_unit(bits: u32) -> f32 {
+    (bits as f32 * (1.0 / 4_294_967_296.0)).min(ONE_MINUS_EPSILON)
+}
+
+/// Element `index` of a pseudo-random permutation of `0..length` chosen by
+/// `seed`, without storing the permutation (Kensler 2013).
+fn permutation_element(mut index: u32, length: u32, seed: u32) -> u32 {
+    let mut mask = length.wrapping_sub(1);
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+    loop {
+        index ^= seed;
+        index = index.wrapping_mul(0xe170_893d);
+        index ^= seed >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= seed >> 8;
+        index = index.wrapping_mul(0x0929_eb3f);
+        index ^= seed >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | seed >> 27);
+        index = index.wrapping_mul(0x6935_fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dc_b303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e50_1cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860_a3df);
+        index &= mask;
+        index ^= index >> 5;
+        if index < length {
+            return index.wrapping_add(seed) % length;
+        }
+    }
+}
+
+/// Uniform random values, each sample being seeded from its pixel and
+/// index.
+pub struct IndependentSampler {
+    seed: u64,
+    rng: SmallRng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        IndependentSampler { seed, rng: SmallRng::seed_from_u64(seed) }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.rng = SmallRng::seed_from_u64(hash(&[u64::from(x), u64::from(y), u64::from(index), self.seed]));
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.rng.gen::<f32>().min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/// Jittered strata: each dimension is cut in as many strata as there are
+/// samples per pixel (a grid for pairs of dimensions), and each sample of a
+/// pixel falls in a different stratum, shuffled independently for every
+/// dimension.
+pub struct StratifiedSampler {
+    x_strata: u32,
+    y_strata: u32,
+    seed: u64,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> StratifiedSampler {
+        let x_strata = ((samples_per_pixel as f32).sqrt() as u32).max(1);
+        let y_strata = samples_per_pixel.div_ceil(x_strata);
+        StratifiedSampler { x_strata, y_strata, seed, pixel: (0, 0), index: 0, dimension: 0 }
+    }
+
+    fn next_hash(&mut self) -> u64 {
+        let (x, y) = self.pixel;
+        let hash = hash(&[u64::from(x), u64::from(y), u64::from(self.dimension), self.seed]);
+        self.dimension += 1;
+        hash
+    }
+
+    /// Stratum of the current sample among `count`, and its jitter.
+    fn stratum(&mut self) -> (u32, u64) {
+        let count = self.x_strata * self.y_strata;
+        let hash = self.next_hash();
+        let stratum = permutation_element(self.index % count, count, hash as u32);
+        (stratum, mix_bits(hash ^ u64::from(self.index)))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = (x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (stratum, jitter) = self.stratum();
+        let count = (self.x_strata * self.y_strata) as f32;
+        ((stratum as f32 + bits_to_unit(jitter as u32)) / count).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let (stratum, jitter) = self.stratum();
+        let (x, y) = (stratum % self.x_strata, stratum / self.x_strata);
+        (
+            ((x as f32 + bits_to_unit(jitter as u32)) / self.x_strata as f32).min(ONE_MINUS_EPSILON),
+            ((y as f32 + bits_to_unit((jitter >> 32) as u32)) / self.y_strata as f32).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+/// Bases of the Halton dimensions; later dimensions fall back to hashed
+/// random values.
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+/// Radical inverse of `index` in `base`, each digit being permuted
+/// depending on the digits before it (Owen scrambling), with all digits
+/// down to the precision of an `f32` scrambled.
+fn owen_scrambled_radical_inverse(base: u32, mut index: u64, seed: u32) -> f32 {
+    let inverse_base = 1.0 / f64::from(base);
+    let mut inverse_base_power = 1.0;
+    let mut reversed_digits: u64 = 0;
+    while inverse_base_power > 1e-8 {
+        let next = index / u64::from(base);
+        let digit = (index - next * u64::from(base)) as u32;
+        let digit_seed = mix_bits(u64::from(seed) ^ reversed_digits) as u32;
+        let digit = permutation_element(digit, base, digit_seed);
+        reversed_digits = reversed_digits * u64::from(base) + u64::from(digit);
+        inverse_base_power *= inverse_base;
+        index = next;
+    }
+    ((reversed_digits as f64 * inverse_base_power) as f32).min(ONE_MINUS_EPSILON)
+}
+
+/// Halton sequence with an Owen scrambling drawn for every pixel and
+/// dimension, so that neighbouring pixels are decorrelated.
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        HaltonSampler { seed, pixel: (0, 0), index: 0, dimension: 0 }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = (x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (x, y) = self.pixel;
+        let hash = hash(&[u64::from(x), u64::from(y), u64::from(self.dimension), self.seed]);
+        let value = match PRIMES.get(self.dimension as usize) {
+            Some(base) => owen_scrambled_radical_inverse(*base, u64::from(self.index), hash as u32),
+            None => bits_to_unit(mix_bits(hash ^ u64::from(self.index)) as u32),
+        };
+        self.dimension += 1;
+        value
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/// Owen scrambling of the bits of a sample, from most to least
+/// significant, by a hash-based nested uniform permutation (Burley 2020).
+fn fast_owen_scramble(mut v: u32, seed: u32) -> u32 {
+    v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20_adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x0552_6c56);
+    v ^= v.wrapping_mul(0x53a2_2864);
+    v.reverse_bits()
+}
+
+/// Bits of point `index` of the first (`dimension` 0) or second
+/// (`dimension` 1) dimension of the Sobol sequence.
+fn sobol_bits(index: u32, dimension: u32) -> u32 {
+    if dimension == 0 {
+        return index.reverse_bits();
+    }
+    let mut bits = 0;
+    let mut column: u32 = 1 << 31;
+    let mut index = index;
+    while index != 0 {
+        if index & 1 == 1 {
+            bits ^= column;
+        }
+        column ^= column >> 1;
+        index >>= 1;
+    }
+    bits
+}
+
+/// Owen-scrambled Sobol points: every pair of dimensions is a scrambled
+/// two-dimensional Sobol net whose points are shuffled independently
+/// between pairs. Best with a power of two samples per pixel; samples past
+/// that count are shuffled within blocks of as many samples.
+pub struct SobolSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32,
+}
+
+impl SobolSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> SobolSampler {
+        SobolSampler { samples_per_pixel: samples_per_pixel.max(1), seed, pixel: (0, 0), index: 0, dimension: 0 }
+    }
+
+    /// Scrambling hash of the next dimensions and the shuffled index of the
+    /// current sample.
+    fn next_point(&mut self, dimensions: u32) -> (u64, u32) {
+        let (x, y) = self.pixel;
+        let hash = hash(&[u64::from(x), u64::from(y), u64::from(self.dimension), self.seed]);
+        self.dimension += dimensions;
+        let length = self.samples_per_pixel;
+        let block = self.index - self.index % length;
+        let index = block.wrapping_add(permutation_element(self.index % length, length, hash as u32 ^ block));
+        (mix_bits(hash), index)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = (x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (hash, index) = self.next_point(1);
+        bits_to_unit(fast_owen_scramble(sobol_bits(index, 0), hash as u32))
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let (hash, index) = self.next_point(2);
+        (
+            bits_to_unit(fast_owen_scramble(sobol_bits(index, 0), hash as u32)),
+            bits_to_unit(fast_owen_scramble(sobol_bits(index, 1), (hash >> 32) as u32)),
+        )
+    }
+}
+
+/// Sampler of the given kind for `samples_per_pixel` samples of each pixel.
+pub fn create_sampler(kind: SamplerKind, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler> {
+    match kind {
+        SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+        SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+        SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+        SamplerKind::Sobol => Box::new(SobolSampler::new(samples_per_pixel, seed)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol,
+    ];
+
+    #[test]
+    fn test_permutation_element() {
+        for length in &[1, 5, 16, 100] {
+            let mut seen = vec![false; *length as usize];
+            for index in 0..*length {
+                let element = permutation_element(index, *length, 0x1234_5678);
+                assert!(!seen[element as usize]);
+                seen[element as usize] = true;
+            }
+        }
+    }
+    #[test]
+    fn test_samples_in_unit_interval() {
+        for kind in KINDS.iter() {
+            let mut sampler = create_sampler(*kind, 16, 7);
+            for index in 0..16 {
+                sampler.start_pixel_sample(3, 4, index);
+                for _ in 0..40 {
+                    let value = sampler.get_1d();
+                    assert!((0.0..1.0).contains(&value));
+                }
+            }
+        }
+    }
+    #[test]
+    fn test_samples_are_stratified() {
+        for kind in &[SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol] {
+            let mut sampler = create_sampler(*kind, 16, 7);
+            // Dimensions of the Halton sequence past the first are in base 3
+            // or more, where 16 samples are not stratified in 16.
+            let dimensions = if *kind == SamplerKind::Halton { 1 } else { 3 };
+            let mut strata = vec![[0; 16]; dimensions];
+            for index in 0..16 {
+                sampler.start_pixel_sample(3, 4, index);
+                for stratum in strata.iter_mut() {
+                    stratum[(sampler.get_1d() * 16.0) as usize] += 1;
+                }
+            }
+            // Each dimension of 16 samples falls in each of 16 strata once.
+            for stratum in strata.iter() {
+                assert!(stratum.iter().all(|count| *count == 1), "{:?} {:?}", kind, stratum);
+            }
+        }
+    }
+    #[test]
+    fn test_sobol_past_samples_per_pixel() {
+        let mut sampler = SobolSampler::new(16, 7);
+        let mut strata = [0; 16];
+        for index in 16..32 {
+            sampler.start_pixel_sample(3, 4, index);
+            strata[(sampler.get_1d() * 16.0) as usize] += 1;
+        }
+        // The next 16 samples are stratified like the first ones.
+        assert!(strata.iter().all(|count| *count == 1), "{:?}", strata);
+    }
+    #[test]
+    fn test_low_discrepancy_reduces_error() {
+        let squared_error = |kind: SamplerKind| {
+            let mut sampler = create_sampler(kind, 64, 11);
+            let mut total = 0.0;
+            for pixel in 0..64 {
+                let mut estimate = 0.0;
+                for index in 0..64 {
+                    sampler.start_pixel_sample(pixel, 0, index);
+                    let (x, y) = sampler.get_2d();
+                    estimate += x * y / 64.0;
+                }
+                total += (estimate - 0.25) * (estimate - 0.25);
+            }
+            total
+        };
+        let independent = squared_error(SamplerKind::Independent);
+        for kind in &[SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol] {
+            assert!(squared_error(*kind) < 0.1 * independent);
+        }
+    }
+}
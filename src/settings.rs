@@ -0,0 +1,589 @@
+/// The built-in scenes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SceneKind {
+    /// The field of random spheres of `random_scene`.
+    Random,
+    /// A line-up of the available materials, from `material_scene`.
+    Materials,
+}
+
+/// The projections of the camera, see the `camera` module.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CameraKind {
+    /// Thin lens perspective.
+    Perspective,
+    /// Parallel projection.
+    Orthographic,
+    /// Equidistant fisheye seeing the half space in front of it.
+    Fisheye,
+    /// Full 360° panorama in equirectangular projection.
+    Equirectangular,
+}
+
+/// The stereo rigs, see `camera::StereoCamera`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StereoKind {
+    /// Perspective eyes with parallel axes, side by side.
+    Parallel,
+    /// Perspective eyes turned towards the convergence point, side by side.
+    ToeIn,
+    /// Omni-directional stereo panoramas, over/under in a square image.
+    Omnidirectional,
+}
+
+/// The available samplers, see the `sampler` module.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SamplerKind {
+    /// Uniform random values.
+    Independent,
+    /// Jittered strata, shuffled between dimensions.
+    Stratified,
+    /// Owen-scrambled Halton sequence.
+    Halton,
+    /// Owen-scrambled Sobol points.
+    Sobol,
+}
+
+/// The reconstruction filters, see `film::Filter`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    /// Mitchell–Netravali cubic.
+    Mitchell,
+    /// Lanczos windowed sinc.
+    Lanczos,
+}
+
+/// The tone-mapping operators, see `tonemap::PostProcess`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMapKind {
+    /// Clips values above 1.
+    Clamp,
+    /// `x / (1 + x)` on the luminance.
+    Reinhard,
+    /// Reinhard reaching white at a chosen luminance.
+    ReinhardExtended,
+    /// Fitted ACES filmic curve.
+    Aces,
+    /// AgX-style sigmoid, desaturating highlights.
+    Agx,
+}
+
+/// The auxiliary buffers a render can output, see `aov::AovFilm`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AovKind {
+    /// Distance from the camera to the first hit.
+    Depth,
+    /// World position of the first hit.
+    Position,
+    /// Surface normal at the first hit.
+    Normal,
+    /// Reflectance at the first hit.
+    Albedo,
+    /// Index of the object hit first, from 1.
+    ObjectId,
+    /// Number of the material hit first, from 1.
+    MaterialId,
+    /// Light seen directly or after a single bounce.
+    Direct,
+    /// Light after more than one bounce.
+    Indirect,
+    /// Light after a first diffuse bounce. Like the other lobes, it leaves
+    /// out the emitters and sky seen directly, which only `direct` holds.
+    Diffuse,
+    /// Light after a first specular bounce.
+    Specular,
+    /// Light after first going through a surface.
+    Transmission,
+}
+
+impl AovKind {
+    pub const ALL: [AovKind; 11] = [
+        AovKind::Depth, AovKind::Position, AovKind::Normal, AovKind::Albedo, AovKind::ObjectId,
+        AovKind::MaterialId, AovKind::Direct, AovKind::Indirect, AovKind::Diffuse, AovKind::Specular,
+        AovKind::Transmission,
+    ];
+
+    /// Name of the AOV on the command line and in output files.
+    pub fn name(self) -> &'static str {
+        match self {
+            AovKind::Depth => "depth",
+            AovKind::Position => "position",
+            AovKind::Normal => "normal",
+            AovKind::Albedo => "albedo",
+            AovKind::ObjectId => "object-id",
+            AovKind::MaterialId => "material-id",
+            AovKind::Direct => "direct",
+            AovKind::Indirect => "indirect",
+            AovKind::Diffuse => "diffuse",
+            AovKind::Specular => "specular",
+            AovKind::Transmission => "transmission",
+        }
+    }
+}
+
+/// Parameters of a render, as given on the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderSettings {
+    pub x_size: u32,
+    pub y_size: u32,
+    /// Samples per pixel, or the most samples per pixel with adaptive
+    /// sampling.
+    pub s_size: u16,
+    /// Error of the displayed value below which a pixel stops being
+    /// sampled, enabling adaptive sampling.
+    pub adaptive_threshold: Option<f32>,
+    /// Samples taken before a pixel may stop, and between two checks of its
+    /// error.
+    pub min_samples: u16,
+    /// Samples per pixel of each pass over the image. If unset, all of them
+    /// are taken in a single pass, or `min_samples` per pass with a time
+    /// limit, so that the image is whole when the time runs out.
+    pub pass_samples: Option<u16>,
+    /// Seconds after which the render stops taking samples.
+    pub time_limit: Option<f32>,
+    /// PPM file where the image is written after each pass.
+    pub snapshot: Option<String>,
+    /// File where the state of the render is saved after each pass.
+    pub checkpoint: Option<String>,
+    /// Go on from the state saved in the checkpoint file.
+    pub resume: bool,
+    /// PPM file where the number of samples of each pixel is drawn.
+    pub heatmap: Option<String>,
+    /// Trace sampled wavelengths instead of RGB triples, so that dispersive
+    /// dielectrics split light into its spectrum.
+    pub spectral: bool,
+    pub scene: SceneKind,
+    pub camera: CameraKind,
+    /// Render a view for each eye into the same image.
+    pub stereo: Option<StereoKind>,
+    /// Distance between the eyes, in scene units.
+    pub interocular: f32,
+    /// Distance in front of the camera seen at the same place by both
+    /// eyes, the focus distance if unset.
+    pub convergence: Option<f32>,
+    /// Focal length in millimetres of a physical perspective camera, whose
+    /// sensor, f-number, shutter and ISO then set the field of view, the
+    /// depth of field and the exposure. The scene is then taken to be in
+    /// metres, so that the aperture is `focal_length / f_number / 1000`
+    /// scene units wide. Otherwise the camera sees 20° through a lens of
+    /// the size that suits the scene.
+    pub focal_length: Option<f32>,
+    /// Width and height of the sensor in millimetres.
+    pub sensor: (f32, f32),
+    pub f_number: f32,
+    /// Exposure time in seconds.
+    pub shutter: f32,
+    pub iso: f32,
+    /// Blades of the aperture, which is round with fewer than 3.
+    pub blades: u32,
+    /// Rotation of the aperture blades in degrees.
+    pub blade_rotation: f32,
+    /// Darkening of the corners, from 0 to 1 for the natural cos⁴ falloff.
+    pub vignetting: f32,
+    /// Clipping of the aperture by the lens barrel at the corners, from 0
+    /// to 1, see `camera::Aperture`.
+    pub cat_eye: f32,
+    pub sampler: SamplerKind,
+    pub filter: FilterKind,
+    /// Radius of the filter in pixels, the usual one of the filter if unset.
+    pub filter_radius: Option<f32>,
+    /// Denoise the image, guided by its albedo, normal and depth.
+    pub denoise: bool,
+    /// Exposure in stops, applied before tone mapping.
+    pub exposure: f32,
+    pub tone_map: ToneMapKind,
+    /// Luminance mapped to white by the extended Reinhard operator.
+    pub white_point: f32,
+    /// Dither the image before quantising it to 8 bits.
+    pub dither: bool,
+    /// Auxiliary buffers to output along with the image.
+    pub aovs: Vec<AovKind>,
+    /// Where the AOVs are written: a multi-layer OpenEXR file if it ends
+    /// with `.exr`, otherwise the prefix of one OpenEXR file per AOV.
+    pub aov_output: Option<String>,
+    /// Seed of all the randomness of the render, including the layout of
+    /// the random scene.
+    pub seed: u64,
+    /// Number of rendering threads, which does not change the image.
+    pub threads: usize,
+    /// Show neither progress nor statistics.
+    pub quiet: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            x_size: 500,
+            y_size: 400,
+            s_size: 100,
+            adaptive_threshold: None,
+            min_samples: 16,
+            pass_samples: None,
+            time_limit: None,
+            snapshot: None,
+            checkpoint: None,
+            resume: false,
+            heatmap: None,
+            spectral: false,
+            scene: SceneKind::Random,
+            camera: CameraKind::Perspective,
+            stereo: None,
+            interocular: 0.065,
+            convergence: None,
+            focal_length: None,
+            sensor: (36.0, 24.0),
+            f_number: 5.6,
+            shutter: 0.001,
+            iso: 100.0,
+            blades: 0,
+            blade_rotation: 0.0,
+            vignetting: 0.0,
+            cat_eye: 0.0,
+            sampler: SamplerKind::Sobol,
+            filter: FilterKind::Gaussian,
+            filter_radius: None,
+            denoise: false,
+            exposure: 0.0,
+            tone_map: ToneMapKind::Clamp,
+            white_point: 4.0,
+            dither: false,
+            aovs: Vec::new(),
+            aov_output: None,
+            seed: 0,
+            threads: std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+            quiet: false,
+        }
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing value for {}", flag))?;
+    value.parse().map_err(|_| format!("invalid value for {}: {}", flag, value))
+}
+
+/// Seconds written either as a number or as a fraction such as `1/250`.
+fn parse_seconds(flag: &str, value: Option<String>) -> Result<f32, String> {
+    let value: String = parse_value(flag, value)?;
+    let seconds = match value.split_once('/') {
+        Some((numerator, denominator)) => numerator.parse::<f32>().ok()
+            .zip(denominator.parse::<f32>().ok())
+            .map(|(numerator, denominator)| numerator / denominator),
+        None => value.parse().ok(),
+    };
+    seconds.ok_or_else(|| format!("invalid value for {}: {}", flag, value))
+}
+
+/// Width and height such as `36x24`.
+fn parse_size(flag: &str, value: Option<String>) -> Result<(f32, f32), String> {
+    let value: String = parse_value(flag, value)?;
+    value.split_once('x')
+        .and_then(|(width, height)| width.parse().ok().zip(height.parse().ok()))
+        .ok_or_else(|| format!("invalid value for {}: {}", flag, value))
+}
+
+impl RenderSettings {
+    /// Settings from command line arguments (without the program name).
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<RenderSettings, String> {
+        let mut settings = RenderSettings::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--width" => settings.x_size = parse_value(&arg, args.next())?,
+                "--height" => settings.y_size = parse_value(&arg, args.next())?,
+                "--samples" => settings.s_size = parse_value(&arg, args.next())?,
+                "--adaptive" => settings.adaptive_threshold = Some(parse_value(&arg, args.next())?),
+                "--min-samples" => settings.min_samples = parse_value(&arg, args.next())?,
+                "--pass-samples" => settings.pass_samples = Some(parse_value(&arg, args.next())?),
+                "--time-limit" => settings.time_limit = Some(parse_value(&arg, args.next())?),
+                "--snapshot" => settings.snapshot = Some(parse_value(&arg, args.next())?),
+                "--checkpoint" => settings.checkpoint = Some(parse_value(&arg, args.next())?),
+                "--resume" => settings.resume = true,
+                "--heatmap" => settings.heatmap = Some(parse_value(&arg, args.next())?),
+                "--filter" => {
+                    settings.filter = match args.next().as_deref() {
+                        Some("box") => FilterKind::Box,
+                        Some("tent") => FilterKind::Tent,
+                        Some("gaussian") => FilterKind::Gaussian,
+                        Some("mitchell") => FilterKind::Mitchell,
+                        Some("lanczos") => FilterKind::Lanczos,
+                        _ => return Err(String::from(
+                            "--filter expects box, tent, gaussian, mitchell or lanczos"
+                        )),
+                    }
+                }
+                "--filter-radius" => settings.filter_radius = Some(parse_value(&arg, args.next())?),
+                "--denoise" => settings.denoise = true,
+                "--exposure" => settings.exposure = parse_value(&arg, args.next())?,
+                "--tone-map" => {
+                    settings.tone_map = match args.next().as_deref() {
+                        Some("clamp") => ToneMapKind::Clamp,
+                        Some("reinhard") => ToneMapKind::Reinhard,
+                        Some("reinhard-extended") => ToneMapKind::ReinhardExtended,
+                        Some("aces") => ToneMapKind::Aces,
+                        Some("agx") => ToneMapKind::Agx,
+                        _ => return Err(String::from(
+                            "--tone-map expects clamp, reinhard, reinhard-extended, aces or agx"
+                        )),
+                    }
+                }
+                "--white-point" => settings.white_point = parse_value(&arg, args.next())?,
+                "--dither" => settings.dither = true,
+                "--aovs" => {
+                    let names: String = parse_value(&arg, args.next())?;
+                    settings.aovs = if names == "all" {
+                        AovKind::ALL.to_vec()
+                    } else {
+                        names.split(',').map(|name| {
+                            AovKind::ALL.iter().copied().find(|kind| kind.name() == name)
+                                .ok_or_else(|| format!("unknown AOV: {}", name))
+                        }).collect::<Result<_, _>>()?
+                    };
+                }
+                "--aov-output" => settings.aov_output = Some(parse_value(&arg, args.next())?),
+                "--seed" => settings.seed = parse_value(&arg, args.next())?,
+                "--threads" => settings.threads = parse_value(&arg, args.next())?,
+                "--spectral" => settings.spectral = true,
+                "--quiet" => settings.quiet = true,
+                "--scene" => {
+                    settings.scene = match args.next().as_deref() {
+                        Some("random") => SceneKind::Random,
+                        Some("materials") => SceneKind::Materials,
+                        _ => return Err(String::from("--scene expects random or materials")),
+                    }
+                }
+                "--camera" => {
+                    settings.camera = match args.next().as_deref() {
+                        Some("perspective") => CameraKind::Perspective,
+                        Some("orthographic") => CameraKind::Orthographic,
+                        Some("fisheye") => CameraKind::Fisheye,
+                        Some("panorama") | Some("equirectangular") => CameraKind::Equirectangular,
+                        _ => return Err(String::from(
+                            "--camera expects perspective, orthographic, fisheye or panorama"
+                        )),
+                    }
+                }
+                "--stereo" => {
+                    settings.stereo = match args.next().as_deref() {
+                        Some("parallel") => Some(StereoKind::Parallel),
+                        Some("toe-in") => Some(StereoKind::ToeIn),
+                        Some("ods") => Some(StereoKind::Omnidirectional),
+                        _ => return Err(String::from("--stereo expects parallel, toe-in or ods")),
+                    }
+                }
+                "--interocular" => settings.interocular = parse_value(&arg, args.next())?,
+                "--convergence" => settings.convergence = Some(parse_value(&arg, args.next())?),
+                "--focal-length" => settings.focal_length = Some(parse_value(&arg, args.next())?),
+                "--sensor" => settings.sensor = parse_size(&arg, args.next())?,
+                "--f-stop" => settings.f_number = parse_value(&arg, args.next())?,
+                "--shutter" => settings.shutter = parse_seconds(&arg, args.next())?,
+                "--iso" => settings.iso = parse_value(&arg, args.next())?,
+                "--blades" => settings.blades = parse_value(&arg, args.next())?,
+                "--blade-rotation" => settings.blade_rotation = parse_value(&arg, args.next())?,
+                "--vignetting" => settings.vignetting = parse_value(&arg, args.next())?,
+                "--cat-eye" => settings.cat_eye = parse_value(&arg, args.next())?,
+                "--sampler" => {
+                    settings.sampler = match args.next().as_deref() {
+                        Some("independent") => SamplerKind::Independent,
+                        Some("stratified") => SamplerKind::Stratified,
+                        Some("halton") => SamplerKind::Halton,
+                        Some("sobol") => SamplerKind::Sobol,
+                        _ => return Err(String::from(
+                            "--sampler expects independent, stratified, halton or sobol"
+                        )),
+                    }
+                }
+                _ => return Err(format!("unknown argument: {}", arg)),
+            }
+        }
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Checks that the settings make sense together, as those from the
+    /// command line always do; settings built in code may not.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.x_size == 0 || self.y_size == 0 || self.s_size == 0 || self.threads == 0 {
+            return Err(String::from("width, height, samples and threads must be positive"));
+        }
+        if self.pass_samples == Some(0) {
+            return Err(String::from("passes must take samples"));
+        }
+        if self.time_limit.is_some_and(|seconds| !seconds.is_finite() || seconds < 0.0) {
+            return Err(String::from("the time limit must be a number of seconds"));
+        }
+        if self.resume && self.checkpoint.is_none() {
+            return Err(String::from("--resume needs a --checkpoint to resume from"));
+        }
+        match (self.stereo, self.camera) {
+            (None, _)
+            | (Some(StereoKind::Parallel | StereoKind::ToeIn), CameraKind::Perspective)
+            | (Some(StereoKind::Omnidirectional), CameraKind::Equirectangular) => {}
+            _ => return Err(String::from(
+                "parallel and toe-in stereo need the perspective camera, ods stereo the panorama"
+            )),
+        }
+        if self.stereo == Some(StereoKind::Omnidirectional) && self.x_size != self.y_size {
+            return Err(String::from("ods stereo needs a square image"));
+        }
+        if !self.interocular.is_finite() || self.interocular < 0.0 {
+            return Err(String::from("the interocular distance must be a distance"));
+        }
+        if self.convergence.is_some_and(|distance| distance.is_nan() || distance <= 0.0) {
+            return Err(String::from("the convergence distance must be positive"));
+        }
+        let positive = |value: f32| value.is_finite() && value > 0.0;
+        if self.focal_length.is_some_and(|focal_length| !positive(focal_length))
+            || !positive(self.sensor.0) || !positive(self.sensor.1)
+            || !positive(self.f_number) || !positive(self.shutter) || !positive(self.iso)
+        {
+            return Err(String::from("the focal length, sensor, f-stop, shutter and ISO must be positive"));
+        }
+        if self.focal_length.is_some() && self.camera != CameraKind::Perspective {
+            return Err(String::from("--focal-length needs the perspective camera"));
+        }
+        if !self.blade_rotation.is_finite()
+            || !(0.0..=1.0).contains(&self.vignetting) || !(0.0..=1.0).contains(&self.cat_eye)
+        {
+            return Err(String::from("vignetting and cat-eye go from 0 to 1"));
+        }
+        if self.min_samples < 2 {
+            return Err(String::from("at least 2 minimum samples are needed to estimate the error"));
+        }
+        if self.adaptive_threshold.is_some_and(|threshold| threshold.is_nan() || threshold <= 0.0) {
+            return Err(String::from("the adaptive threshold must be positive"));
+        }
+        if self.filter_radius.is_some_and(|radius| radius.is_nan() || radius <= 0.0) {
+            return Err(String::from("the filter radius must be positive"));
+        }
+        if !self.exposure.is_finite() {
+            return Err(String::from("the exposure must be finite"));
+        }
+        if self.white_point.is_nan() || self.white_point <= 0.0 {
+            return Err(String::from("the white point must be positive"));
+        }
+        if self.aovs.is_empty() != self.aov_output.is_none() {
+            return Err(String::from("--aovs and --aov-output go together"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<RenderSettings, String> {
+        RenderSettings::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_default_settings() {
+        assert_eq!(parse(&[]), Ok(RenderSettings::default()));
+    }
+    #[test]
+    fn test_parse_settings() {
+        let settings = parse(&[
+            "--width", "64", "--height", "32", "--samples", "8", "--spectral", "--scene", "materials",
+            "--camera", "fisheye", "--sampler", "halton", "--seed", "42", "--threads", "3",
+            "--adaptive", "0.01", "--min-samples", "4", "--heatmap", "counts.ppm",
+            "--filter", "mitchell", "--filter-radius", "1.5",
+            "--aovs", "depth,object-id", "--aov-output", "passes.exr",
+            "--pass-samples", "2", "--snapshot", "progress.ppm", "--checkpoint", "render.ckpt", "--resume",
+            "--time-limit", "300", "--quiet",
+            "--denoise", "--exposure", "-0.5", "--tone-map", "reinhard-extended", "--white-point", "8", "--dither",
+        ]).unwrap();
+        assert_eq!(settings.x_size, 64);
+        assert_eq!(settings.y_size, 32);
+        assert_eq!(settings.s_size, 8);
+        assert!(settings.spectral);
+        assert_eq!(settings.scene, SceneKind::Materials);
+        assert_eq!(settings.camera, CameraKind::Fisheye);
+        assert_eq!(settings.sampler, SamplerKind::Halton);
+        assert_eq!(settings.seed, 42);
+        assert_eq!(settings.threads, 3);
+        assert_eq!(settings.adaptive_threshold, Some(0.01));
+        assert_eq!(settings.min_samples, 4);
+        assert_eq!(settings.filter, FilterKind::Mitchell);
+        assert_eq!(settings.filter_radius, Some(1.5));
+        assert_eq!(settings.heatmap.as_deref(), Some("counts.ppm"));
+        assert_eq!(settings.pass_samples, Some(2));
+        assert_eq!(settings.snapshot.as_deref(), Some("progress.ppm"));
+        assert_eq!(settings.checkpoint.as_deref(), Some("render.ckpt"));
+        assert!(settings.resume);
+        assert_eq!(settings.time_limit, Some(300.0));
+        assert!(settings.quiet);
+        assert!(settings.denoise);
+        assert_eq!(settings.exposure, -0.5);
+        assert_eq!(settings.tone_map, ToneMapKind::ReinhardExtended);
+        assert_eq!(settings.white_point, 8.0);
+        assert!(settings.dither);
+        assert_eq!(settings.aovs, vec![AovKind::Depth, AovKind::ObjectId]);
+        assert_eq!(settings.aov_output.as_deref(), Some("passes.exr"));
+        assert_eq!(parse(&["--camera", "panorama"]).unwrap().camera, CameraKind::Equirectangular);
+        let stereo = parse(&["--stereo", "toe-in", "--interocular", "0.2", "--convergence", "12"]).unwrap();
+        assert_eq!(stereo.stereo, Some(StereoKind::ToeIn));
+        assert_eq!(stereo.interocular, 0.2);
+        assert_eq!(stereo.convergence, Some(12.0));
+        let physical = parse(&[
+            "--focal-length", "85", "--sensor", "23.5x15.6", "--f-stop", "1.4", "--shutter", "1/250", "--iso", "400",
+            "--blades", "7", "--blade-rotation", "15", "--vignetting", "0.5", "--cat-eye", "0.3",
+        ]).unwrap();
+        assert_eq!(physical.focal_length, Some(85.0));
+        assert_eq!(physical.sensor, (23.5, 15.6));
+        assert_eq!(physical.f_number, 1.4);
+        assert_eq!(physical.shutter, 0.004);
+        assert_eq!(physical.iso, 400.0);
+        assert_eq!(physical.blades, 7);
+        assert_eq!(physical.blade_rotation, 15.0);
+        assert_eq!(physical.vignetting, 0.5);
+        assert_eq!(physical.cat_eye, 0.3);
+        assert_eq!(parse(&["--shutter", "0.5"]).unwrap().shutter, 0.5);
+        let ods = parse(&["--camera", "equirectangular", "--stereo", "ods", "--width", "400", "--height", "400"]);
+        assert_eq!(ods.unwrap().stereo, Some(StereoKind::Omnidirectional));
+        assert_eq!(parse(&["--aovs", "all", "--aov-output", "pass"]).unwrap().aovs.len(), AovKind::ALL.len());
+    }
+    #[test]
+    fn test_parse_settings_errors() {
+        assert!(parse(&["--width"]).is_err());
+        assert!(parse(&["--width", "wide"]).is_err());
+        assert!(parse(&["--samples", "0"]).is_err());
+        assert!(parse(&["--threads", "0"]).is_err());
+        assert!(parse(&["--seed", "-1"]).is_err());
+        assert!(parse(&["--adaptive", "0"]).is_err());
+        assert!(parse(&["--min-samples", "1"]).is_err());
+        assert!(parse(&["--pass-samples", "0"]).is_err());
+        assert!(parse(&["--resume"]).is_err());
+        assert!(parse(&["--time-limit", "-1"]).is_err());
+        assert!(parse(&["--time-limit", "NaN"]).is_err());
+        assert!(parse(&["--filter", "sharp"]).is_err());
+        assert!(parse(&["--filter-radius", "-1"]).is_err());
+        assert!(parse(&["--tone-map", "linear"]).is_err());
+        assert!(parse(&["--exposure", "inf"]).is_err());
+        assert!(parse(&["--white-point", "0"]).is_err());
+        assert!(parse(&["--aovs", "depth,shadow", "--aov-output", "passes.exr"]).is_err());
+        assert!(parse(&["--aovs", "depth"]).is_err());
+        assert!(parse(&["--aov-output", "passes.exr"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+        assert!(parse(&["--scene", "moon"]).is_err());
+        assert!(parse(&["--camera", "pinhole"]).is_err());
+        assert!(parse(&["--stereo", "anaglyph"]).is_err());
+        assert!(parse(&["--stereo", "ods"]).is_err());
+        assert!(parse(&["--camera", "equirectangular", "--stereo", "ods"]).is_err());
+        assert!(parse(&["--camera", "fisheye", "--stereo", "parallel"]).is_err());
+        assert!(parse(&["--interocular", "-0.1"]).is_err());
+        assert!(parse(&["--focal-length", "0"]).is_err());
+        assert!(parse(&["--focal-length", "50", "--camera", "fisheye"]).is_err());
+        assert!(parse(&["--sensor", "36"]).is_err());
+        assert!(parse(&["--sensor", "36x-24"]).is_err());
+        assert!(parse(&["--shutter", "1/0"]).is_err());
+        assert!(parse(&["--shutter", "fast"]).is_err());
+        assert!(parse(&["--f-stop", "0"]).is_err());
+        assert!(parse(&["--iso", "-100"]).is_err());
+        assert!(parse(&["--vignetting", "2"]).is_err());
+        assert!(parse(&["--cat-eye", "-0.5"]).is_err());
+        assert!(parse(&["--convergence", "0"]).is_err());
+        assert!(parse(&["--sampler", "dice"]).is_err());
+    }
+}
@@ -0,0 +1,325 @@
+use std::ops;
+
+use crate::color::Color;
+
+/// Shortest wavelength traced in spectral mode, in nanometres.
+pub const LAMBDA_MIN: f32 = 380.0;
+/// Longest wavelength traced in spectral mode, in nanometres.
+pub const LAMBDA_MAX: f32 = 720.0;
+/// Number of wavelengths carried by a path.
+pub const N_SAMPLES: usize = 4;
+
+/// Integrals of the CIE 1931 matching functions over
+/// `[LAMBDA_MIN, LAMBDA_MAX]`, used to white-balance the XYZ estimate.
+const CIE_INTEGRALS: [f32; 3] = [106.760_72, 106.911_87, 106.825_33];
+
+/// Linear sRGB (D65 primaries) from CIE XYZ.
+const XYZ_TO_SRGB: [[f32; 3]; 3] = [
+    [3.240_454_2, -1.537_138_5, -0.498_531_4],
+    [-0.969_266, 1.876_010_8, 0.041_556],
+    [0.055_643_4, -0.204_025_9, 1.057_225_2],
+];
+
+/// Basis spectra of Smits, "An RGB-to-Spectrum Conversion for
+/// Reflectances" (1999), in ten bins over `[LAMBDA_MIN, LAMBDA_MAX]`.
+const SMITS_WHITE: [f32; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f32; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f32; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f32; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f32; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f32; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f32; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+fn gaussian(x: f32, mu: f32, sigma_low: f32, sigma_high: f32) -> f32 {
+    let sigma = if x < mu { sigma_low } else { sigma_high };
+    let t = (x - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 colour matching functions at `lambda` nanometres, using the
+/// multi-lobe fit of Wyman, Sloan and Shirley (2013).
+pub fn cie_xyz(lambda: f32) -> [f32; 3] {
+    [
+        1.056 * gaussian(lambda, 599.8, 37.9, 31.0)
+            + 0.362 * gaussian(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * gaussian(lambda, 501.1, 20.4, 26.2),
+        0.821 * gaussian(lambda, 568.8, 46.9, 40.5)
+            + 0.286 * gaussian(lambda, 530.9, 16.3, 31.1),
+        1.217 * gaussian(lambda, 437.0, 11.8, 36.0)
+            + 0.681 * gaussian(lambda, 459.0, 26.0, 13.8),
+    ]
+}
+
+fn xyz_to_srgb(xyz: [f32; 3]) -> Color {
+    let row = |r: usize| {
+        XYZ_TO_SRGB[r][0] * xyz[0] + XYZ_TO_SRGB[r][1] * xyz[1] + XYZ_TO_SRGB[r][2] * xyz[2]
+    };
+    Color::new(row(0), row(1), row(2))
+}
+
+/// Value at `lambda` of a smooth spectrum whose colour is `color`
+/// (Smits 1999).
+pub fn rgb_to_spectrum(color: Color, lambda: f32) -> f32 {
+    let bin = (((lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 10.0) as usize).min(9);
+    let (red, green, blue) = (color.red, color.green, color.blue);
+    if red <= green && red <= blue {
+        let mut value = red * SMITS_WHITE[bin];
+        if green <= blue {
+            value += (green - red) * SMITS_CYAN[bin] + (blue - green) * SMITS_BLUE[bin];
+        } else {
+            value += (blue - red) * SMITS_CYAN[bin] + (green - blue) * SMITS_GREEN[bin];
+        }
+        value
+    } else if green <= red && green <= blue {
+        let mut value = green * SMITS_WHITE[bin];
+        if red <= blue {
+            value += (red - green) * SMITS_MAGENTA[bin] + (blue - red) * SMITS_BLUE[bin];
+        } else {
+            value += (blue - green) * SMITS_MAGENTA[bin] + (red - blue) * SMITS_RED[bin];
+        }
+        value
+    } else {
+        let mut value = blue * SMITS_WHITE[bin];
+        if red <= green {
+            value += (red - blue) * SMITS_YELLOW[bin] + (green - red) * SMITS_GREEN[bin];
+        } else {
+            value += (green - blue) * SMITS_YELLOW[bin] + (red - green) * SMITS_RED[bin];
+        }
+        value
+    }
+}
+
+/// The wavelengths carried by one path, sampled by hero wavelength
+/// sampling (Wilkie et al. 2014): the hero is uniform over the visible
+/// range and the others are evenly rotated from it.
+#[derive(Copy, Clone, Debug)]
+pub struct SampledWavelengths {
+    pub lambda: [f32; N_SAMPLES],
+    pub pdf: [f32; N_SAMPLES],
+}
+
+impl SampledWavelengths {
+    /// Wavelengths for the uniform random number `u` in `[0, 1)`.
+    pub fn sample_uniform(u: f32) -> SampledWavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let delta = range / N_SAMPLES as f32;
+        let mut lambda = [0.0; N_SAMPLES];
+        lambda[0] = LAMBDA_MIN + u * range;
+        for i in 1..N_SAMPLES {
+            lambda[i] = lambda[i - 1] + delta;
+            if lambda[i] > LAMBDA_MAX {
+                lambda[i] -= range;
+            }
+        }
+        SampledWavelengths { lambda, pdf: [1.0 / range; N_SAMPLES] }
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    /// Keeps only the hero wavelength, once the path took a direction that
+    /// depends on it (e.g. refraction through a dispersive dielectric).
+    pub fn terminate_secondary(&mut self) {
+        if self.is_secondary_terminated() {
+            return;
+        }
+        for pdf in self.pdf.iter_mut().skip(1) {
+            *pdf = 0.0;
+        }
+        self.pdf[0] /= N_SAMPLES as f32;
+    }
+
+    pub fn is_secondary_terminated(&self) -> bool {
+        self.pdf[1..].iter().all(|pdf| *pdf == 0.0)
+    }
+}
+
+/// A spectral quantity evaluated at the wavelengths of a
+/// `SampledWavelengths`.
+#[derive(Copy, Clone, Debug)]
+pub struct SampledSpectrum {
+    pub values: [f32; N_SAMPLES],
+}
+
+impl ops::Add for SampledSpectrum {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let mut values = self.values;
+        for (value, other) in values.iter_mut().zip(other.values.iter()) {
+            *value += other;
+        }
+        SampledSpectrum { values }
+    }
+}
+
+impl ops::AddAssign for SampledSpectrum {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl ops::Mul for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let mut values = self.values;
+        for (value, other) in values.iter_mut().zip(other.values.iter()) {
+            *value *= other;
+        }
+        SampledSpectrum { values }
+    }
+}
+
+impl ops::MulAssign for SampledSpectrum {
+    fn mul_assign(&mut self, other: Self) {
+        *self = *self * other;
+    }
+}
+
+impl ops::MulAssign<f32> for SampledSpectrum {
+    fn mul_assign(&mut self, other: f32) {
+        for value in self.values.iter_mut() {
+            *value *= other;
+        }
+    }
+}
+
+/// Quantity transported along a path by the integrator: an RGB `Color`,
+/// or a `SampledSpectrum` at the wavelengths of the path.
+pub trait Radiance:
+    Copy + ops::Add<Output = Self> + ops::AddAssign + ops::Mul<Output = Self> + ops::MulAssign + ops::MulAssign<f32>
+{
+    /// Wavelengths carried by the path, `()` when tracing RGB.
+    type Wavelengths;
+
+    fn from_color(color: Color, wavelengths: &Self::Wavelengths) -> Self;
+    fn to_color(self, wavelengths: &Self::Wavelengths) -> Color;
+    /// Wavelength given to the rays of the path, if any.
+    fn wavelength(wavelengths: &Self::Wavelengths) -> Option<f32>;
+    fn terminate_secondary(wavelengths: &mut Self::Wavelengths);
+    /// Largest component, steering Russian roulette.
+    fn max_component(&self) -> f32;
+}
+
+impl Radiance for Color {
+    type Wavelengths = ();
+
+    fn from_color(color: Color, _wavelengths: &()) -> Color {
+        color
+    }
+    fn to_color(self, _wavelengths: &()) -> Color {
+        self
+    }
+    fn wavelength(_wavelengths: &()) -> Option<f32> {
+        None
+    }
+    fn terminate_secondary(_wavelengths: &mut ()) {}
+    fn max_component(&self) -> f32 {
+        self.red.max(self.green).max(self.blue)
+    }
+}
+
+impl Radiance for SampledSpectrum {
+    type Wavelengths = SampledWavelengths;
+
+    fn from_color(color: Color, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        let mut values = [0.0; N_SAMPLES];
+        for (value, lambda) in values.iter_mut().zip(wavelengths.lambda.iter()) {
+            *value = rgb_to_spectrum(color, *lambda);
+        }
+        SampledSpectrum { values }
+    }
+
+    /// Monte Carlo estimate of the linear sRGB colour of the spectrum,
+    /// white-balanced so that a constant unit spectrum maps to white.
+    fn to_color(self, wavelengths: &SampledWavelengths) -> Color {
+        let mut xyz = [0.0; 3];
+        for i in 0..N_SAMPLES {
+            if wavelengths.pdf[i] == 0.0 {
+                continue;
+            }
+            let cmf = cie_xyz(wavelengths.lambda[i]);
+            for (channel, value) in xyz.iter_mut().zip(cmf.iter()) {
+                *channel += self.values[i] * value / wavelengths.pdf[i];
+            }
+        }
+        for channel in xyz.iter_mut() {
+            *channel /= N_SAMPLES as f32;
+        }
+        let mut rgb = xyz_to_srgb(xyz);
+        rgb /= xyz_to_srgb(CIE_INTEGRALS);
+        rgb
+    }
+    fn wavelength(wavelengths: &SampledWavelengths) -> Option<f32> {
+        Some(wavelengths.hero())
+    }
+    fn terminate_secondary(wavelengths: &mut SampledWavelengths) {
+        wavelengths.terminate_secondary();
+    }
+    fn max_component(&self) -> f32 {
+        self.values.iter().cloned().fold(0.0, f32::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cie_integrals() {
+        let steps = 3400;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / steps as f32;
+        let mut integrals = [0.0_f32; 3];
+        for i in 0..steps {
+            let xyz = cie_xyz(LAMBDA_MIN + (i as f32 + 0.5) * step);
+            for c in 0..3 {
+                integrals[c] += xyz[c] * step;
+            }
+        }
+        for c in 0..3 {
+            assert!((integrals[c] - CIE_INTEGRALS[c]).abs() < 0.05);
+        }
+    }
+    #[test]
+    fn test_sampled_wavelengths() {
+        let mut wavelengths = SampledWavelengths::sample_uniform(0.9);
+        for lambda in wavelengths.lambda.iter() {
+            assert!(*lambda >= LAMBDA_MIN && *lambda <= LAMBDA_MAX);
+        }
+        assert!(!wavelengths.is_secondary_terminated());
+        wavelengths.terminate_secondary();
+        assert!(wavelengths.is_secondary_terminated());
+        let pdf = wavelengths.pdf[0];
+        wavelengths.terminate_secondary();
+        assert_eq!(wavelengths.pdf[0], pdf);
+    }
+    #[test]
+    fn test_white_round_trip() {
+        let mut sum = Color::black();
+        let n = 1000;
+        for i in 0..n {
+            let wavelengths = SampledWavelengths::sample_uniform((i as f32 + 0.5) / n as f32);
+            sum += SampledSpectrum::from_color(Color::white(), &wavelengths).to_color(&wavelengths);
+        }
+        sum /= n as f32;
+        assert!((sum.red - 1.0).abs() < 0.01);
+        assert!((sum.green - 1.0).abs() < 0.01);
+        assert!((sum.blue - 1.0).abs() < 0.01);
+    }
+    #[test]
+    fn test_red_round_trip() {
+        let mut sum = Color::black();
+        let n = 1000;
+        for i in 0..n {
+            let wavelengths = SampledWavelengths::sample_uniform((i as f32 + 0.5) / n as f32);
+            let red = Color::new(1.0, 0.0, 0.0);
+            sum += SampledSpectrum::from_color(red, &wavelengths).to_color(&wavelengths);
+        }
+        sum /= n as f32;
+        assert!(sum.red > 0.8);
+        assert!(sum.red > 4.0 * sum.green.abs());
+        assert!(sum.red > 4.0 * sum.blue.abs());
+    }
+}
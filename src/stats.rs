@@ -0,0 +1,176 @@
+use std::fmt;
+use std::ops::{AddAssign, Range};
+use std::time::Duration;
+
+/// Samples and rays traced by a part of a render.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RayCounts {
+    pub samples: u64,
+    /// Rays from the camera, one per sample.
+    pub primary: u64,
+    /// Rays scattered along the paths, and between the steps of random
+    /// walks through media.
+    pub secondary: u64,
+    /// Rays towards lights testing whether they are seen. Always 0: lights
+    /// are only found by the paths that hit them, without light sampling.
+    pub shadow: u64,
+    /// Ray-object intersection tests, counted as the rays are traced. The
+    /// scene has no acceleration structure, so that every ray tests every
+    /// object, once more past each partially transparent surface crossed.
+    pub intersection_tests: u64,
+}
+
+impl AddAssign for RayCounts {
+    fn add_assign(&mut self, other: RayCounts) {
+        self.samples += other.samples;
+        self.primary += other.primary;
+        self.secondary += other.secondary;
+        self.shadow += other.shadow;
+        self.intersection_tests += other.intersection_tests;
+    }
+}
+
+impl RayCounts {
+    pub fn rays(&self) -> u64 {
+        self.primary + self.secondary + self.shadow
+    }
+
+    /// Rays traced per path, on average.
+    pub fn average_path_length(&self) -> f64 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        self.rays() as f64 / self.samples as f64
+    }
+}
+
+/// Counts and timings of a render, with the time spent in each phase.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RenderStatistics {
+    pub rays: RayCounts,
+    /// Building the scene.
+    pub scene_time: Duration,
+    /// Tracing the samples of the passes.
+    pub render_time: Duration,
+    /// Denoising and post-processing the image of each pass.
+    pub finish_time: Duration,
+    /// Writing the image and the other outputs, when they are.
+    pub output_time: Duration,
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.3} s", duration.as_secs_f64())
+}
+
+impl fmt::Display for RenderStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rays = &self.rays;
+        let per_second = |count: u64| {
+            let seconds = self.render_time.as_secs_f64();
+            if seconds > 0.0 { count as f64 / seconds } else { 0.0 }
+        };
+        writeln!(f, "samples:             {} ({:.0}/s)", rays.samples, per_second(rays.samples))?;
+        writeln!(f, "rays:                {} ({:.0}/s)", rays.rays(), per_second(rays.rays()))?;
+        writeln!(f, "  primary:           {}", rays.primary)?;
+        writeln!(f, "  secondary:         {}", rays.secondary)?;
+        writeln!(f, "  shadow:            {} (no light sampling)", rays.shadow)?;
+        writeln!(f, "average path length: {:.2} rays", rays.average_path_length())?;
+        writeln!(f, "intersection tests:  {} (no BVH)", rays.intersection_tests)?;
+        writeln!(f, "scene:               {}", seconds(self.scene_time))?;
+        writeln!(f, "render:              {}", seconds(self.render_time))?;
+        writeln!(f, "post-processing:     {}", seconds(self.finish_time))?;
+        write!(f, "output:              {}", seconds(self.output_time))
+    }
+}
+
+/// How far a render has gone, reported as its tiles are finished.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress {
+    /// Samples per pixel of the current pass.
+    pub pass: Range<u16>,
+    /// Samples per pixel of the whole render.
+    pub s_size: u16,
+    /// Samples per pixel already taken when the render started, if it was
+    /// resumed.
+    pub start: u16,
+    /// Tiles of the current pass finished, out of `tiles`.
+    pub tiles_done: usize,
+    pub tiles: usize,
+    /// Samples taken since the render started.
+    pub samples: u64,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// Part of the samples per pixel taken, from 0 to 1.
+    pub fn fraction(&self) -> f64 {
+        let pass = f64::from(self.pass.end - self.pass.start) * self.tiles_done as f64 / self.tiles.max(1) as f64;
+        ((f64::from(self.pass.start) + pass) / f64::from(self.s_size.max(1))).min(1.0)
+    }
+
+    pub fn samples_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 { self.samples as f64 / seconds } else { 0.0 }
+    }
+
+    /// Time left if the render goes on at the same pace. Pixels stopped by
+    /// adaptive sampling make it shorter.
+    pub fn eta(&self) -> Option<Duration> {
+        let start = f64::from(self.start) / f64::from(self.s_size.max(1));
+        let done = self.fraction() - start;
+        if done <= 0.0 {
+            return None;
+        }
+        Some(self.elapsed.mul_f64((1.0 - self.fraction()) / done))
+    }
+}
+
+/// One line progress bar, such as
+/// `[##########----------]  50.0%  pass 8..16 of 32 spp  1234567 samples/s  ETA 0:12`.
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const WIDTH: usize = 20;
+        let fraction = self.fraction();
+        let filled = (fraction * WIDTH as f64) as usize;
+        write!(
+            f, "[{}{}] {:5.1}%  pass {}..{} of {} spp  {:.0} samples/s  ETA ",
+            "#".repeat(filled), "-".repeat(WIDTH - filled), 100.0 * fraction,
+            self.pass.start, self.pass.end, self.s_size, self.samples_per_second(),
+        )?;
+        match self.eta() {
+            Some(eta) => {
+                let seconds = eta.as_secs();
+                write!(f, "{}:{:02}", seconds / 60, seconds % 60)
+            }
+            None => write!(f, "-"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress() {
+        let progress = Progress {
+            pass: 8..16,
+            s_size: 32,
+            start: 0,
+            tiles_done: 5,
+            tiles: 10,
+            samples: 1200,
+            elapsed: Duration::from_secs(6),
+        };
+        assert!((progress.fraction() - 0.375).abs() < 1e-9);
+        assert_eq!(progress.samples_per_second(), 200.0);
+        let eta = |progress: &Progress| progress.eta().map(|eta| eta.as_secs_f64().round());
+        assert_eq!(eta(&progress), Some(10.0));
+        assert!(progress.to_string().starts_with("[#######-------------]  37.5%"));
+        // A resumed render is timed from where it started.
+        let resumed = Progress { start: 4, ..progress };
+        assert_eq!(eta(&resumed), Some(15.0));
+        let started = Progress { pass: 4..8, tiles_done: 0, ..resumed };
+        assert_eq!(started.eta(), None);
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::Arc;
+
+use crate::vec3::Vec3;
+use crate::color::Color;
+
+pub trait Texture {
+    fn value(&self, u: f32, v: f32, p_vect: &Vec3) -> Color;
+
+    /// Scalar read of the texture, for parameters such as roughness.
+    fn value_f32(&self, u: f32, v: f32, p_vect: &Vec3) -> f32 {
+        let color = self.value(u, v, p_vect);
+        (color.red + color.green + color.blue) / 3.0
+    }
+}
+
+/// A texture shared between materials and threads.
+pub type TextureRef = Arc<dyn Texture + Send + Sync>;
+
+pub struct ConstantTexture {
+    pub color: Color,
+}
+
+impl ConstantTexture {
+    pub fn new(color: Color) -> ConstantTexture {
+        ConstantTexture { color }
+    }
+    pub fn new_ref(color: Color) -> TextureRef {
+        Arc::new(ConstantTexture::new(color))
+    }
+    pub fn new_f32_ref(value: f32) -> TextureRef {
+        Arc::new(ConstantTexture::new(Color::new(value, value, value)))
+    }
+}
+
+impl Texture for ConstantTexture {
+    fn value(&self, _u: f32, _v: f32, _p_vect: &Vec3) -> Color {
+        self.color
+    }
+}
+
+/// Solid checker alternating between two textures every `1 / frequency`
+/// scene units along each axis.
+pub struct CheckerTexture {
+    pub odd: TextureRef,
+    pub even: TextureRef,
+    pub frequency: f32,
+}
+
+impl CheckerTexture {
+    pub fn new(odd: TextureRef, even: TextureRef, frequency: f32) -> CheckerTexture {
+        CheckerTexture { odd, even, frequency }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f32, v: f32, p_vect: &Vec3) -> Color {
+        let cell = (self.frequency * p_vect.x).floor()
+            + (self.frequency * p_vect.y).floor()
+            + (self.frequency * p_vect.z).floor();
+        if cell.rem_euclid(2.0) == 0.0 {
+            self.even.value(u, v, p_vect)
+        } else {
+            self.odd.value(u, v, p_vect)
+        }
+    }
+}
+
+/// Texture read from an image with bilinear filtering, repeating outside of
+/// `[0, 1]`. `v = 1` is the first row of `pixels`.
+pub struct ImageTexture {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> ImageTexture {
+        assert_eq!(pixels.len(), width * height, "image size does not match its pixels");
+        ImageTexture { width, height, pixels }
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _p_vect: &Vec3) -> Color {
+        let x = u * self.width as f32 - 0.5;
+        let y = (1.0 - v) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        (1.0 - dy) * ((1.0 - dx) * self.texel(x0, y0) + dx * self.texel(x0 + 1, y0))
+            + dy * ((1.0 - dx) * self.texel(x0, y0 + 1) + dx * self.texel(x0 + 1, y0 + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checker_texture() {
+        let checker = CheckerTexture::new(
+            ConstantTexture::new_f32_ref(0.0),
+            ConstantTexture::new_f32_ref(1.0),
+            2.0,
+        );
+        assert_eq!(checker.value_f32(0.0, 0.0, &Vec3::new(0.1, 0.1, 0.1)), 1.0);
+        assert_eq!(checker.value_f32(0.0, 0.0, &Vec3::new(0.6, 0.1, 0.1)), 0.0);
+        assert_eq!(checker.value_f32(0.0, 0.0, &Vec3::new(-0.1, 0.1, 0.1)), 0.0);
+        assert_eq!(checker.value_f32(0.0, 0.0, &Vec3::new(-0.6, 0.1, 0.1)), 1.0);
+    }
+    #[test]
+    fn test_image_texture() {
+        let image = ImageTexture::new(2, 1, vec![Color::black(), Color::white()]);
+        let p_vect = Vec3::new_zero();
+        assert_eq!(image.value_f32(0.25, 0.5, &p_vect), 0.0);
+        assert_eq!(image.value_f32(0.75, 0.5, &p_vect), 1.0);
+        assert_eq!(image.value_f32(0.5, 0.5, &p_vect), 0.5);
+        assert_eq!(image.value_f32(1.75, -3.5, &p_vect), 1.0);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::camera;
+use crate::color::{Color, ColorU8};
+use crate::sampler::hash_float;
+use crate::settings::{RenderSettings, ToneMapKind};
+
+/// Turns the linear colours of the film into 8-bit sRGB: exposure, tone
+/// mapping, the sRGB transfer function, then optional dithering before
+/// quantisation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PostProcess {
+    /// Exposure in stops.
+    pub exposure: f32,
+    pub tone_map: ToneMapKind,
+    /// Luminance mapped to white by the extended Reinhard operator.
+    pub white_point: f32,
+    pub dither: bool,
+    pub seed: u64,
+}
+
+impl PostProcess {
+    /// Post-processing of `settings`, exposing as the physical camera does
+    /// if there is one.
+    pub fn new(settings: &RenderSettings) -> PostProcess {
+        let photographic = match settings.focal_length {
+            Some(_) => camera::exposure(settings.f_number, settings.shutter, settings.iso),
+            None => 0.0,
+        };
+        PostProcess {
+            exposure: settings.exposure + photographic,
+            tone_map: settings.tone_map,
+            white_point: settings.white_point,
+            dither: settings.dither,
+            seed: settings.seed,
+        }
+    }
+
+    /// Display colour in `[0, 1]`, before the sRGB encoding.
+    pub fn tone_map(&self, color: Color) -> Color {
+        let color = 2f32.powf(self.exposure) * clamp_negative(color);
+        let mapped = match self.tone_map {
+            ToneMapKind::Clamp => color,
+            ToneMapKind::Reinhard => {
+                let luminance = luminance(color);
+                color / (1.0 + luminance)
+            }
+            ToneMapKind::ReinhardExtended => {
+                let luminance = luminance(color);
+                let white = self.white_point * self.white_point;
+                color * ((1.0 + luminance / white) / (1.0 + luminance))
+            }
+            ToneMapKind::Aces => aces_fitted(color),
+            ToneMapKind::Agx => agx(color),
+        };
+        Color::new(
+            mapped.red.clamp(0.0, 1.0),
+            mapped.green.clamp(0.0, 1.0),
+            mapped.blue.clamp(0.0, 1.0),
+        )
+    }
+
+    /// Encoded display value of a grey of the given luminance.
+    pub fn display_luminance(&self, luminance: f32) -> f32 {
+        srgb_oetf(self.tone_map(Color::new(luminance, luminance, luminance)).green)
+    }
+
+    /// 8-bit sRGB value of the linear colour of pixel `(i, j)`. The pixel
+    /// position only seeds the dither.
+    pub fn encode(&self, color: Color, i: u32, j: u32) -> ColorU8 {
+        let color = self.tone_map(color);
+        let channels = [color.red, color.green, color.blue].map(srgb_oetf);
+        let mut values = [0.0; 3];
+        for (channel, value) in values.iter_mut().enumerate() {
+            *value = f32::from(u8::MAX) * channels[channel];
+            if self.dither {
+                // Triangular noise of one step hides banding in gradients.
+                let key = [u64::from(i), u64::from(j), channel as u64, self.seed];
+                let (a, b) = (hash_float(&key), hash_float(&[key[0], key[1], key[2], !key[3]]));
+                *value += a - b;
+            }
+            *value = value.clamp(0.0, f32::from(u8::MAX));
+        }
+        ColorU8::make_from_color(Color::new(values[0], values[1], values[2]))
+    }
+}
+
+fn clamp_negative(color: Color) -> Color {
+    Color::new(color.red.max(0.0), color.green.max(0.0), color.blue.max(0.0))
+}
+
+fn luminance(color: Color) -> f32 {
+    0.2126 * color.red + 0.7152 * color.green + 0.0722 * color.blue
+}
+
+/// sRGB encoding of a linear value in `[0, 1]`.
+pub fn srgb_oetf(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        12.92 * value
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn mul_matrix(matrix: &[[f32; 3]; 3], color: Color) -> Color {
+    let row = |row: &[f32; 3]| row[0] * color.red + row[1] * color.green + row[2] * color.blue;
+    Color::new(row(&matrix[0]), row(&matrix[1]), row(&matrix[2]))
+}
+
+/// ACES filmic curve, Stephen Hill's fit of the reference rendering and
+/// output transforms, working from and to linear sRGB.
+fn aces_fitted(color: Color) -> Color {
+    const INPUT: [[f32; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    const OUTPUT: [[f32; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+    let curve = |v: f32| (v * (v + 0.024_578_6) - 0.000_090_537) / (v * (0.983_729 * v + 0.432_951) + 0.238_081);
+    let color = mul_matrix(&INPUT, color);
+    let color = Color::new(curve(color.red), curve(color.green), curve(color.blue));
+    mul_matrix(&OUTPUT, color)
+}
+
+/// AgX-style curve: the colour is pulled towards the achromatic axis,
+/// mapped in log space by a sigmoid fitted to Troy Sobotka's AgX, then
+/// pushed back, so bright saturated colours desaturate as they approach
+/// white.
+fn agx(color: Color) -> Color {
+    const INSET: [[f32; 3]; 3] = [
+        [0.842_479_1, 0.078_433_6, 0.079_223_7],
+        [0.042_328_2, 0.878_468_6, 0.079_166_1],
+        [0.042_375_4, 0.078_843_6, 0.879_142_9],
+    ];
+    const OUTSET: [[f32; 3]; 3] = [
+        [1.196_879, -0.098_021_4, -0.099_029_7],
+        [-0.052_896_8, 1.151_903_1, -0.098_917_1],
+        [-0.052_971_6, -0.098_043_4, 1.151_073_7],
+    ];
+    const MIN_EV: f32 = -12.473_93;
+    const MAX_EV: f32 = 4.026_069;
+    let contrast = |v: f32| {
+        let v = ((v.max(1e-10).log2() - MIN_EV) / (MAX_EV - MIN_EV)).clamp(0.0, 1.0);
+        let (v2, v4) = (v * v, v * v * v * v);
+        15.5 * v4 * v2 - 40.14 * v4 * v + 31.96 * v4 - 6.868 * v2 * v + 0.4298 * v2 + 0.1191 * v - 0.002_32
+    };
+    let color = mul_matrix(&INSET, color);
+    let color = Color::new(contrast(color.red), contrast(color.green), contrast(color.blue));
+    // The curve gives display values, which are made linear again for the
+    // sRGB encoding.
+    let color = mul_matrix(&OUTSET, color);
+    Color::new(
+        color.red.max(0.0).powf(2.2),
+        color.green.max(0.0).powf(2.2),
+        color.blue.max(0.0).powf(2.2),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post_process(tone_map: ToneMapKind) -> PostProcess {
+        PostProcess { tone_map, ..PostProcess::new(&RenderSettings::default()) }
+    }
+
+    #[test]
+    fn test_srgb_oetf() {
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb_oetf(0.18) - 0.4613).abs() < 1e-3);
+        // Both pieces meet at the threshold.
+        assert!((srgb_oetf(0.003_130_8) - srgb_oetf(0.003_130_9)).abs() < 1e-5);
+    }
+    #[test]
+    fn test_tone_maps() {
+        let kinds = [
+            ToneMapKind::Clamp, ToneMapKind::Reinhard, ToneMapKind::ReinhardExtended,
+            ToneMapKind::Aces, ToneMapKind::Agx,
+        ];
+        for kind in &kinds {
+            let post_process = post_process(*kind);
+            let mut previous = -1.0;
+            for stop in -8..8 {
+                let value = 2f32.powi(stop);
+                let mapped = post_process.tone_map(Color::new(value, value, value));
+                assert!(mapped.green >= previous, "{:?} is not monotonic", kind);
+                assert!(mapped.red <= 1.0 && mapped.blue >= 0.0);
+                previous = mapped.green;
+            }
+            assert!(post_process.tone_map(Color::black()).green < 0.01);
+        }
+        // Highlights are compressed instead of clipped.
+        let reinhard = post_process(ToneMapKind::Reinhard);
+        assert!(reinhard.tone_map(Color::white() * 4.0).green < reinhard.tone_map(Color::white() * 8.0).green);
+        let extended = PostProcess { white_point: 4.0, ..post_process(ToneMapKind::ReinhardExtended) };
+        assert!((extended.tone_map(Color::white() * 4.0).green - 1.0).abs() < 1e-5);
+        let aces = post_process(ToneMapKind::Aces);
+        assert!(aces.tone_map(Color::white() * 4.0).green < aces.tone_map(Color::white() * 8.0).green);
+    }
+    #[test]
+    fn test_exposure() {
+        let post_process = PostProcess { exposure: 1.0, ..post_process(ToneMapKind::Clamp) };
+        assert!((post_process.tone_map(Color::white() * 0.25).red - 0.5).abs() < 1e-6);
+        // Two stops over the sunny 16 rule at f/8, plus the stop asked for.
+        let settings = RenderSettings {
+            focal_length: Some(50.0), f_number: 8.0, shutter: 0.01, iso: 100.0, exposure: 1.0,
+            ..RenderSettings::default()
+        };
+        assert!((PostProcess::new(&settings).exposure - 3.0).abs() < 1e-5);
+        let digital = RenderSettings { focal_length: None, ..settings };
+        assert_eq!(PostProcess::new(&digital).exposure, 1.0);
+    }
+    #[test]
+    fn test_dither() {
+        let plain = post_process(ToneMapKind::Clamp);
+        let gray = Color::white() * 0.2;
+        let value = plain.encode(gray, 0, 0).green;
+        assert_eq!(value, (255.0 * srgb_oetf(0.2)).round() as u8);
+        let dithered = PostProcess { dither: true, ..plain };
+        let mut sum = 0.0;
+        for i in 0..256 {
+            let dithered_value = dithered.encode(gray, i, 3).green;
+            assert!((i32::from(dithered_value) - i32::from(value)).abs() <= 1);
+            sum += f32::from(dithered_value);
+        }
+        // The dither keeps the average of the exact value.
+        assert!((sum / 256.0 - 255.0 * srgb_oetf(0.2)).abs() < 0.1);
+    }
+}
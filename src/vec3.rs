@@ -46,6 +46,18 @@ impl Vec3 {
                y: self.y.sqrt(),
                z: self.z.sqrt() }
     }
+
+    /// Two unit vectors completing `self` (which must be unit length) into
+    /// an orthonormal basis (Duff et al. 2017).
+    pub fn orthonormal_basis(&self) -> (Vec3, Vec3) {
+        let sign = 1.0_f32.copysign(self.z);
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+        (
+            Vec3::new(1.0 + sign * self.x * self.x * a, sign * b, -sign * self.x),
+            Vec3::new(b, sign + self.y * self.y * a, -self.y),
+        )
+    }
 }
 
 impl fmt::Display for Vec3 {
@@ -240,7 +252,7 @@ mod tests {
     #[test]
     fn test_length_vec3() {
         let s = Vec3::new(2.0, 2.0, 2.0);
-        let twelve = 12.0 as f32;
+        let twelve = 12.0_f32;
         assert_eq!(s.length(), twelve.sqrt());
     }
     #[test]
@@ -294,4 +306,19 @@ mod tests {
         let value = 1.0 / u.length();
         assert_eq!(unit, Vec3::new(value, value, value));
     }
+    #[test]
+    fn test_orthonormal_basis_vec3() {
+        for n in &[
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(1.0, 2.0, 3.0).make_unit_vector(),
+        ] {
+            let (t, b) = n.orthonormal_basis();
+            assert!((t.length() - 1.0).abs() < 1e-5);
+            assert!((b.length() - 1.0).abs() < 1e-5);
+            assert!(t.dot(*n).abs() < 1e-5);
+            assert!(b.dot(*n).abs() < 1e-5);
+            assert!(t.dot(b).abs() < 1e-5);
+        }
+    }
 }